@@ -0,0 +1,646 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+
+use crate::savestate::invalid_data;
+
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+// Source syntax:
+//   label:                     defines a label for the address of the next instruction or data
+//   NAME = expression          defines a constant
+//   LD V3, 0x1F                instructions as listed by the disassembler (case insensitive)
+//   db 1, 2, 3 / dw 0x1234     bytes and big endian words
+//   include "file.asm"         inserts another file, relative to the including file
+//   ; comment
+// Expressions consist of numbers (decimal, 0x hexadecimal, 0b binary), labels, constants,
+// parentheses and the operators + - * / % & | ^ << >> ~.
+// Disassembler listings can be assembled again, the address and raw byte columns are ignored.
+
+// Line of source code after includes have been resolved
+struct SourceLine {
+    file: PathBuf,
+    number: usize,
+    text: String,
+}
+
+// Statement whose size is known after the first pass
+enum Statement<'a> {
+    Instruction(&'a str, Vec<&'a str>), // mnemonic, operands
+    Data(usize, Vec<&'a str>),          // bytes per value, values
+}
+
+// Assembles the source, includes are loaded relative to the file
+pub fn assemble(source: &str, file: &Path, program_address: usize) -> Result<Vec<u8>, Error> {
+    let mut lines = Vec::new();
+    read_lines(source, file, 0, &mut lines)?;
+
+    // First pass: addresses of labels and constant definitions
+    let mut symbols: HashMap<String, Symbol> = HashMap::new();
+    let mut statements = Vec::new();
+    let mut address = program_address;
+    for line in lines.iter() {
+        let error = |message: String| line_error(line, &message);
+        let mut text = strip_listing_columns(strip_comment(&line.text));
+
+        if let Some(colon) = text.find(':') {
+            let label = text[..colon].trim();
+            if is_identifier(label) {
+                define_symbol(&mut symbols, label, Symbol::Label(address)).map_err(error)?;
+                text = text[(colon + 1)..].trim();
+            }
+        }
+        if text.is_empty() {
+            continue;
+        }
+
+        if let Some(equals) = text.find('=') {
+            let name = text[..equals].trim();
+            if is_identifier(name) {
+                let value = text[(equals + 1)..].trim();
+                define_symbol(&mut symbols, name, Symbol::Constant(value)).map_err(error)?;
+                continue;
+            }
+        }
+
+        let statement = parse_statement(text);
+        address += match &statement {
+            Statement::Instruction(mnemonic, operands) => {
+                get_instruction_length(mnemonic, operands)
+            }
+            Statement::Data(size, values) => size * values.len(),
+        };
+        statements.push((line, statement));
+    }
+
+    // Second pass: encoding
+    let mut rom = Vec::new();
+    for (line, statement) in statements.iter() {
+        let evaluate = |expression: &str| evaluate(expression, &symbols, 0);
+        let result = match statement {
+            Statement::Instruction(mnemonic, operands) => {
+                encode_instruction(mnemonic, operands, &evaluate)
+            }
+            Statement::Data(size, values) => encode_data(*size, values, &evaluate),
+        };
+        rom.extend(result.map_err(|message| line_error(line, &message))?);
+    }
+
+    Ok(rom)
+}
+
+pub fn assemble_file(file: &Path, program_address: usize) -> Result<Vec<u8>, Error> {
+    assemble(&fs::read_to_string(file)?, file, program_address)
+}
+
+enum Symbol<'a> {
+    Label(usize),
+    Constant(&'a str), // unevaluated, constants may refer to labels defined later
+}
+
+fn define_symbol<'a>(
+    symbols: &mut HashMap<String, Symbol<'a>>,
+    name: &str,
+    symbol: Symbol<'a>,
+) -> Result<(), String> {
+    if symbols.insert(name.to_lowercase(), symbol).is_some() {
+        return Err(format!("\"{}\" is already defined", name));
+    }
+    Ok(())
+}
+
+fn read_lines(
+    source: &str,
+    file: &Path,
+    depth: usize,
+    lines: &mut Vec<SourceLine>,
+) -> Result<(), Error> {
+    for (index, text) in source.lines().enumerate() {
+        let line = SourceLine {
+            file: file.to_path_buf(),
+            number: index + 1,
+            text: text.to_string(),
+        };
+
+        let statement = strip_comment(text);
+        if statement.len() > 8 && statement[..8].eq_ignore_ascii_case("include ") {
+            let name = statement[8..].trim().trim_matches('"');
+            let include = file.parent().unwrap_or(Path::new("")).join(name);
+            if depth >= MAX_INCLUDE_DEPTH {
+                return Err(line_error(&line, "Includes are nested too deeply"));
+            }
+
+            let source = fs::read_to_string(&include).map_err(|error| {
+                line_error(&line, &format!("Cannot include \"{}\": {}", name, error))
+            })?;
+            read_lines(&source, &include, depth + 1, lines)?;
+        } else {
+            lines.push(line);
+        }
+    }
+
+    Ok(())
+}
+
+fn line_error(line: &SourceLine, message: &str) -> Error {
+    invalid_data(&format!(
+        "{file}:{line}: {message}",
+        file = line.file.display(),
+        line = line.number,
+        message = message
+    ))
+}
+
+fn strip_comment(text: &str) -> &str {
+    match text.find(';') {
+        Some(comment) => text[..comment].trim(),
+        None => text.trim(),
+    }
+}
+
+// Listing lines start with the address followed by up to four raw bytes in uppercase
+fn strip_listing_columns(text: &str) -> &str {
+    if !text.starts_with("0x") {
+        return text;
+    }
+
+    let mut rest = text;
+    let mut columns = 0;
+    while let Some(token) = rest.split_whitespace().next() {
+        let is_address = columns == 0;
+        let is_byte = token.len() == 2
+            && token
+                .chars()
+                .all(|c| c.is_ascii_hexdigit() && !c.is_ascii_lowercase());
+        if !(is_address || (is_byte && columns <= 4)) {
+            break;
+        }
+        rest = rest.trim_start()[token.len()..].trim_start();
+        columns += 1;
+    }
+    rest
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+fn parse_statement(text: &str) -> Statement<'_> {
+    let (mnemonic, operands) = match text.find(char::is_whitespace) {
+        Some(space) => (&text[..space], text[space..].trim()),
+        None => (text, ""),
+    };
+    let operands: Vec<&str> = if operands.is_empty() {
+        Vec::new()
+    } else {
+        operands.split(',').map(|operand| operand.trim()).collect()
+    };
+
+    if mnemonic.eq_ignore_ascii_case("db") {
+        Statement::Data(1, operands)
+    } else if mnemonic.eq_ignore_ascii_case("dw") {
+        Statement::Data(2, operands)
+    } else {
+        Statement::Instruction(mnemonic, operands)
+    }
+}
+
+fn get_instruction_length(mnemonic: &str, operands: &[&str]) -> usize {
+    match operands {
+        [i, long]
+            if mnemonic.eq_ignore_ascii_case("ld")
+                && i.eq_ignore_ascii_case("i")
+                && is_long(long) =>
+        {
+            4
+        }
+        _ => 2,
+    }
+}
+
+fn is_long(operand: &str) -> bool {
+    operand.len() > 5 && operand[..5].eq_ignore_ascii_case("long ")
+}
+
+// Operand as expected by an instruction
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Argument {
+    Register(u16),
+    Keyword(&'static str),
+    Value(u16),
+    Long(u16),
+}
+
+fn parse_argument(
+    operand: &str,
+    evaluate: &dyn Fn(&str) -> Result<i64, String>,
+) -> Result<Argument, String> {
+    let upper = operand.to_uppercase();
+    for keyword in ["I", "[I]", "DT", "ST", "K", "F", "HF", "B", "R"].iter() {
+        if upper == *keyword {
+            return Ok(Argument::Keyword(keyword));
+        }
+    }
+    if upper.len() == 2 && upper.starts_with('V') {
+        if let Ok(x) = u16::from_str_radix(&upper[1..], 16) {
+            return Ok(Argument::Register(x));
+        }
+    }
+    if is_long(operand) {
+        let value = evaluate(&operand[5..])?;
+        return check_range(value, 0xFFFF, operand).map(Argument::Long);
+    }
+
+    let value = evaluate(operand)?;
+    // Negative values are stored in two's complement, e.g. for ADD Vx, -1
+    let value = if value < 0 && value >= -0x80 {
+        value + 0x100
+    } else {
+        value
+    };
+    check_range(value, 0xFFFF, operand).map(Argument::Value)
+}
+
+fn check_range(value: i64, maximum: i64, operand: &str) -> Result<u16, String> {
+    if value < 0 || value > maximum {
+        return Err(format!("\"{}\" is out of range ({})", operand, value));
+    }
+    Ok(value as u16)
+}
+
+fn encode_instruction(
+    mnemonic: &str,
+    operands: &[&str],
+    evaluate: &dyn Fn(&str) -> Result<i64, String>,
+) -> Result<Vec<u8>, String> {
+    use self::Argument::*;
+
+    let arguments = operands
+        .iter()
+        .map(|operand| parse_argument(operand, evaluate))
+        .collect::<Result<Vec<Argument>, String>>()?;
+
+    let byte = |value: u16| check_range(value as i64, 0xFF, &value.to_string());
+    let nibble = |value: u16| check_range(value as i64, 0xF, &value.to_string());
+    let address = |value: u16| check_range(value as i64, 0xFFF, &format!("0x{:X}", value));
+
+    let opcode = match (mnemonic.to_uppercase().as_str(), arguments.as_slice()) {
+        ("CLS", []) => 0x00E0,
+        ("RET", []) => 0x00EE,
+        ("SCD", [Value(n)]) => 0x00C0 | nibble(*n)?,
+        ("SCU", [Value(n)]) => 0x00D0 | nibble(*n)?,
+        ("SCR", []) => 0x00FB,
+        ("SCL", []) => 0x00FC,
+        ("EXIT", []) => 0x00FD,
+        ("LOW", []) => 0x00FE,
+        ("HIGH", []) => 0x00FF,
+        ("SYS", [Value(nnn)]) => address(*nnn)?,
+        ("JP", [Value(nnn)]) => 0x1000 | address(*nnn)?,
+        ("JP", [Register(0), Value(nnn)]) => 0xB000 | address(*nnn)?,
+        ("CALL", [Value(nnn)]) => 0x2000 | address(*nnn)?,
+        ("SE", [Register(x), Value(kk)]) => 0x3000 | x << 8 | byte(*kk)?,
+        ("SNE", [Register(x), Value(kk)]) => 0x4000 | x << 8 | byte(*kk)?,
+        ("SE", [Register(x), Register(y)]) => 0x5000 | x << 8 | y << 4,
+        ("SAVE", [Register(x), Register(y)]) => 0x5002 | x << 8 | y << 4,
+        ("LOAD", [Register(x), Register(y)]) => 0x5003 | x << 8 | y << 4,
+        ("LD", [Register(x), Value(kk)]) => 0x6000 | x << 8 | byte(*kk)?,
+        ("ADD", [Register(x), Value(kk)]) => 0x7000 | x << 8 | byte(*kk)?,
+        ("LD", [Register(x), Register(y)]) => 0x8000 | x << 8 | y << 4,
+        ("OR", [Register(x), Register(y)]) => 0x8001 | x << 8 | y << 4,
+        ("AND", [Register(x), Register(y)]) => 0x8002 | x << 8 | y << 4,
+        ("XOR", [Register(x), Register(y)]) => 0x8003 | x << 8 | y << 4,
+        ("ADD", [Register(x), Register(y)]) => 0x8004 | x << 8 | y << 4,
+        ("SUB", [Register(x), Register(y)]) => 0x8005 | x << 8 | y << 4,
+        ("SHR", [Register(x)]) => 0x8006 | x << 8 | x << 4,
+        ("SHR", [Register(x), Register(y)]) => 0x8006 | x << 8 | y << 4,
+        ("SUBN", [Register(x), Register(y)]) => 0x8007 | x << 8 | y << 4,
+        ("SHL", [Register(x)]) => 0x800E | x << 8 | x << 4,
+        ("SHL", [Register(x), Register(y)]) => 0x800E | x << 8 | y << 4,
+        ("SNE", [Register(x), Register(y)]) => 0x9000 | x << 8 | y << 4,
+        ("LD", [Keyword("I"), Value(nnn)]) => 0xA000 | address(*nnn)?,
+        ("LD", [Keyword("I"), Long(nnnn)]) => {
+            return Ok(vec![0xF0, 0x00, (nnnn >> 8) as u8, *nnnn as u8])
+        }
+        ("RND", [Register(x), Value(kk)]) => 0xC000 | x << 8 | byte(*kk)?,
+        ("DRW", [Register(x), Register(y), Value(n)]) => 0xD000 | x << 8 | y << 4 | nibble(*n)?,
+        ("SKP", [Register(x)]) => 0xE09E | x << 8,
+        ("SKNP", [Register(x)]) => 0xE0A1 | x << 8,
+        ("PLANE", [Value(n)]) => 0xF001 | nibble(*n)? << 8,
+        ("AUDIO", []) => 0xF002,
+        ("LD", [Register(x), Keyword("DT")]) => 0xF007 | x << 8,
+        ("LD", [Register(x), Keyword("K")]) => 0xF00A | x << 8,
+        ("LD", [Keyword("DT"), Register(x)]) => 0xF015 | x << 8,
+        ("LD", [Keyword("ST"), Register(x)]) => 0xF018 | x << 8,
+        ("ADD", [Keyword("I"), Register(x)]) => 0xF01E | x << 8,
+        ("LD", [Keyword("F"), Register(x)]) => 0xF029 | x << 8,
+        ("LD", [Keyword("HF"), Register(x)]) => 0xF030 | x << 8,
+        ("LD", [Keyword("B"), Register(x)]) => 0xF033 | x << 8,
+        ("PITCH", [Register(x)]) => 0xF03A | x << 8,
+        ("LD", [Keyword("[I]"), Register(x)]) => 0xF055 | x << 8,
+        ("LD", [Register(x), Keyword("[I]")]) => 0xF065 | x << 8,
+        ("LD", [Keyword("R"), Register(x)]) => 0xF075 | x << 8,
+        ("LD", [Register(x), Keyword("R")]) => 0xF085 | x << 8,
+        _ => {
+            return Err(format!(
+                "Unknown instruction \"{} {}\"",
+                mnemonic,
+                operands.join(", ")
+            ))
+        }
+    };
+
+    Ok(vec![(opcode >> 8) as u8, opcode as u8])
+}
+
+fn encode_data(
+    size: usize,
+    values: &[&str],
+    evaluate: &dyn Fn(&str) -> Result<i64, String>,
+) -> Result<Vec<u8>, String> {
+    let mut data = Vec::new();
+    for value in values {
+        if size == 1 {
+            data.push(check_range(evaluate(value)?, 0xFF, value)? as u8);
+        } else {
+            let word = check_range(evaluate(value)?, 0xFFFF, value)?;
+            data.extend_from_slice(&word.to_be_bytes());
+        }
+    }
+    Ok(data)
+}
+
+fn evaluate(
+    expression: &str,
+    symbols: &HashMap<String, Symbol>,
+    depth: usize,
+) -> Result<i64, String> {
+    if depth > symbols.len() {
+        return Err("Constants refer to each other".to_string());
+    }
+
+    let mut parser = ExpressionParser {
+        text: expression,
+        position: 0,
+        symbols: symbols,
+        depth: depth,
+    };
+    let value = parser.parse_binary(0)?;
+    parser.skip_whitespace();
+    if parser.position < expression.len() {
+        return Err(format!("Invalid expression \"{}\"", expression));
+    }
+    Ok(value)
+}
+
+// Recursive descent parser with operator precedence as in C
+struct ExpressionParser<'a> {
+    text: &'a str,
+    position: usize,
+    symbols: &'a HashMap<String, Symbol<'a>>,
+    depth: usize,
+}
+
+const BINARY_OPERATORS: [(&str, usize); 10] = [
+    ("|", 1),
+    ("^", 2),
+    ("&", 3),
+    ("<<", 4),
+    (">>", 4),
+    ("+", 5),
+    ("-", 5),
+    ("*", 6),
+    ("/", 6),
+    ("%", 6),
+];
+
+impl<'a> ExpressionParser<'a> {
+    fn parse_binary(&mut self, minimum_precedence: usize) -> Result<i64, String> {
+        let mut left = self.parse_unary()?;
+
+        loop {
+            self.skip_whitespace();
+            let rest = &self.text[self.position..];
+            let operator = BINARY_OPERATORS.iter().find(|(operator, precedence)| {
+                rest.starts_with(operator) && *precedence > minimum_precedence
+            });
+            let (operator, precedence) = match operator {
+                Some(operator) => *operator,
+                None => return Ok(left),
+            };
+            self.position += operator.len();
+
+            let right = self.parse_binary(precedence)?;
+            let result = match operator {
+                "|" => Some(left | right),
+                "^" => Some(left ^ right),
+                "&" => Some(left & right),
+                "<<" => Some(left << (right & 63)),
+                ">>" => Some(left >> (right & 63)),
+                "+" => left.checked_add(right),
+                "-" => left.checked_sub(right),
+                "*" => left.checked_mul(right),
+                "/" | "%" if right == 0 => return Err("Division by zero".to_string()),
+                "/" => left.checked_div(right),
+                _ => left.checked_rem(right),
+            };
+            left = result.ok_or_else(|| format!("Overflow in \"{}\"", self.text))?;
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<i64, String> {
+        self.skip_whitespace();
+        let rest = &self.text[self.position..];
+
+        if rest.starts_with('-') {
+            self.position += 1;
+            let value = self.parse_unary()?;
+            return value
+                .checked_neg()
+                .ok_or_else(|| format!("Overflow in \"{}\"", self.text));
+        }
+        if rest.starts_with('~') {
+            self.position += 1;
+            return Ok(!self.parse_unary()?);
+        }
+        if rest.starts_with('(') {
+            self.position += 1;
+            let value = self.parse_binary(0)?;
+            self.skip_whitespace();
+            if !self.text[self.position..].starts_with(')') {
+                return Err(format!("Missing \")\" in \"{}\"", self.text));
+            }
+            self.position += 1;
+            return Ok(value);
+        }
+
+        let length = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        let token = &rest[..length];
+        self.position += length;
+
+        let number = if token.starts_with("0x") || token.starts_with("0X") {
+            i64::from_str_radix(&token[2..], 16).ok()
+        } else if token.starts_with("0b") || token.starts_with("0B") {
+            i64::from_str_radix(&token[2..], 2).ok()
+        } else {
+            token.parse().ok()
+        };
+
+        match (number, self.symbols.get(&token.to_lowercase())) {
+            (Some(number), _) => Ok(number),
+            (None, Some(Symbol::Label(address))) => Ok(*address as i64),
+            (None, Some(Symbol::Constant(expression))) => {
+                evaluate(expression, self.symbols, self.depth + 1)
+            }
+            (None, None) if token.is_empty() => {
+                Err(format!("Invalid expression \"{}\"", self.text))
+            }
+            (None, None) => Err(format!("Unknown label or constant \"{}\"", token)),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.position..];
+        self.position += rest.len() - rest.trim_start().len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Mode;
+    use crate::disassembler::disassemble;
+
+    fn assemble_source(source: &str) -> Result<Vec<u8>, Error> {
+        assemble(source, Path::new("test.asm"), 0x200)
+    }
+
+    #[test]
+    fn test_assemble_instructions() {
+        let rom = assemble_source(
+            "CLS
+            LD V3, 0x1F
+            drw v0, v1, 5
+            LD I, 0x23C
+            JP V0, 0x300
+            LD [I], VA
+            SHR V2
+            ADD V4, -1
+            LD I, long 0x1234",
+        )
+        .unwrap();
+
+        assert_eq!(
+            rom,
+            vec![
+                0x00, 0xE0, 0x63, 0x1F, 0xD0, 0x15, 0xA2, 0x3C, 0xB3, 0x00, 0xFA, 0x55, 0x82, 0x26,
+                0x74, 0xFF, 0xF0, 0x00, 0x12, 0x34
+            ]
+        );
+    }
+
+    #[test]
+    fn test_assemble_labels_constants_and_data() {
+        let rom = assemble_source(
+            "; Forward references are resolved
+            HEIGHT = SPRITE_END - sprite
+            start: LD I, sprite
+            DRW V0, V0, HEIGHT
+            loop: JP loop
+            sprite:
+                db 0b11110000, 0x90 | 0x0F, (1 + 2) * 3
+            SPRITE_END = sprite + 3
+            dw start",
+        )
+        .unwrap();
+
+        assert_eq!(
+            rom,
+            vec![0xA2, 0x06, 0xD0, 0x03, 0x12, 0x04, 0xF0, 0x9F, 0x09, 0x02, 0x00]
+        );
+    }
+
+    #[test]
+    fn test_assemble_include() {
+        let directory = std::env::temp_dir().join("chip-8-emulator-assembler-test");
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("constants.asm"), "VALUE = 0x42\n").unwrap();
+        fs::write(
+            directory.join("main.asm"),
+            "include \"constants.asm\"\nLD V0, VALUE\n",
+        )
+        .unwrap();
+
+        let rom = assemble_file(&directory.join("main.asm"), 0x200).unwrap();
+
+        assert_eq!(rom, vec![0x60, 0x42]);
+    }
+
+    #[test]
+    fn test_assemble_reports_line_numbers() {
+        let error = assemble_source("CLS\n\nLD V0, missing\n").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "test.asm:3: Unknown label or constant \"missing\""
+        );
+
+        let error = assemble_source("LD V0, 0x100").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "test.asm:1: \"256\" is out of range (256)"
+        );
+
+        let error = assemble_source("FOO V0").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "test.asm:1: Unknown instruction \"FOO V0\""
+        );
+
+        let error = assemble_source("a:\na: CLS").unwrap_err();
+        assert_eq!(error.to_string(), "test.asm:2: \"a\" is already defined");
+
+        let error = assemble_source("CLS\nLD V0, 0x7FFFFFFFFFFFFFFF + 1").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "test.asm:2: Overflow in \"0x7FFFFFFFFFFFFFFF + 1\""
+        );
+        for expression in [
+            "0x7FFFFFFFFFFFFFFF * 2",
+            "-0x7FFFFFFFFFFFFFFF - 2",
+            "-(-0x7FFFFFFFFFFFFFFF - 1)",
+            "(-0x7FFFFFFFFFFFFFFF - 1) / -1",
+            "(-0x7FFFFFFFFFFFFFFF - 1) % -1",
+        ]
+        .iter()
+        {
+            let error = assemble_source(&format!("LD V0, {}", expression)).unwrap_err();
+            assert!(error.to_string().contains("Overflow"), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn test_assemble_disassembled_roms() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("roms");
+        for directory in ["demos", "games", "programs"].iter() {
+            for entry in fs::read_dir(root.join(directory)).unwrap() {
+                let path = entry.unwrap().path();
+                let rom = fs::read(&path).unwrap();
+
+                let mut listing = Vec::new();
+                disassemble(&mut listing, &rom, 0x200, Mode::Chip8).unwrap();
+                let source = String::from_utf8(listing).unwrap();
+
+                let result = assemble(&source, &path, 0x200);
+                assert_eq!(result.unwrap(), rom, "{}", path.display());
+            }
+        }
+    }
+}
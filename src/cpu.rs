@@ -1,12 +1,16 @@
 extern crate rand;
-extern crate sdl2;
 
 use std::io::{BufWriter, Error, Read, Write};
 
+use crate::disassembler::disassemble_at;
 use crate::display::DisplayTrait;
+use crate::error::EmulatorError;
 use crate::keypad::KeypadTrait;
-use crate::memory::MemoryTrait;
-use crate::speaker::SpeakerTrait;
+use crate::memory::{MemoryTrait, MEMORY_SIZE, XO_CHIP_MEMORY_SIZE};
+use crate::quirks::Quirks;
+use crate::random::{Random, RandomTrait};
+use crate::savestate::{invalid_data, StateReader, StateWriter};
+use crate::speaker::{SpeakerTrait, AUDIO_PATTERN_BYTES};
 
 // Font data
 const FONT_WIDTH: usize = 5;
@@ -31,26 +35,103 @@ const FONT: [u8; FONT_BYTES] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+// Large font data (SUPER-CHIP), stored right after the regular font
+const LARGE_FONT_WIDTH: usize = 10;
+const LARGE_FONT_BYTES: usize = LARGE_FONT_WIDTH * 16;
+const LARGE_FONT_ADDRESS: usize = FONT_BYTES;
+
+const LARGE_FONT: [u8; LARGE_FONT_BYTES] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+/// Instruction set of the emulated interpreter
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    Chip8,     // Original instruction set
+    SuperChip, // SUPER-CHIP 1.1 extensions (high resolution, scrolling, large sprites)
+    XoChip, // XO-CHIP extensions on top of SUPER-CHIP (64 KiB memory, bit-planes, audio patterns)
+}
+
+impl Mode {
+    /// Parses a name as used on the command line and in movie files
+    pub fn from_name(name: &str) -> Option<Mode> {
+        match name {
+            "chip-8" => Some(Mode::Chip8),
+            "schip" => Some(Mode::SuperChip),
+            "xo-chip" => Some(Mode::XoChip),
+            _ => None,
+        }
+    }
+
+    /// Name as used on the command line and in movie files
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Mode::Chip8 => "chip-8",
+            Mode::SuperChip => "schip",
+            Mode::XoChip => "xo-chip",
+        }
+    }
+}
+
+/// Processor that executes a program on the given devices
 pub trait CpuTrait {
+    /// Copies the fonts and the program to memory, returns the size of the ROM in bytes
     fn load_rom(
         &mut self,
         memory: &mut dyn MemoryTrait,
         rom_reader: &mut dyn Read,
-    ) -> Result<usize, Error>;
+    ) -> Result<usize, EmulatorError>;
+    /// Executes one instruction, a faulting instruction leaves the state unchanged
     fn step(
         &mut self,
-        delta_time: f32,
         memory: &mut dyn MemoryTrait,
         keypad: &mut dyn KeypadTrait,
         display: &mut dyn DisplayTrait,
         speaker: &mut dyn SpeakerTrait,
         debug_cpu: bool,
         debug_memory: bool,
-    );
+    ) -> Result<(), EmulatorError>;
+    /// Decrements the timers, called at the end of every 60 Hz frame
+    fn end_frame(&mut self, speaker: &mut dyn SpeakerTrait);
+    /// Instructions per second
     fn get_clock_rate(&self) -> f32;
-    fn print_debug_info(&self);
+    fn get_mode(&self) -> Mode;
+    /// Whether the program ended with the SUPER-CHIP instruction 00FD
+    fn has_exited(&self) -> bool;
+    fn print_debug_info(&self, memory: &dyn MemoryTrait);
+}
+
+/// Inspection and modification of the processor state (debugger)
+pub trait CpuDebugTrait {
+    fn get_pc(&self) -> usize;
+    fn set_pc(&mut self, pc: usize);
+    fn get_register(&self, x: usize) -> u8;
+    fn set_register(&mut self, x: usize, value: u8);
+    fn get_i(&self) -> u16;
+    fn set_i(&mut self, i: u16);
+    fn get_delay_timer(&self) -> u8;
+    fn set_delay_timer(&mut self, value: u8);
+    fn get_sound_timer(&self) -> u8;
+    fn set_sound_timer(&mut self, value: u8);
+    fn get_stack(&self) -> &[usize];
 }
 
+/// Chip-8 processor, also executes SUPER-CHIP and XO-CHIP instructions depending on the mode
 pub struct Cpu {
     // Program
     opcode: u16, // current opcode (two 8-bit values)
@@ -60,19 +141,31 @@ pub struct Cpu {
     v: [u8; 16], // 16 8-bit general purpose registers
     i: u16,      // 16-bit register for storing memory adresses
 
-    delay_timer: u8,    // 8-bit delay timer (decremented at 60 Hz)
-    delay_timer_f: f32, // float representation of delay_timer
-    sound_timer: u8,    // 8-bit sound timer (decremented at 60 Hz)
-    sound_timer_f: f32, // float representation of sound_timer
+    delay_timer: u8, // 8-bit delay timer (decremented at 60 Hz)
+    sound_timer: u8, // 8-bit sound timer (decremented at 60 Hz)
 
     // Stack
     stack: [usize; 16], // 16 values to store return values of subroutines
     sp: u8,             // 8-bit register that points to the topmost level of the stack
 
+    // SUPER-CHIP
+    rpl: [u8; 16], // user flag registers (HP-48 RPL flags)
+    exited: bool,  // set by 00FD - EXIT
+
+    // Vertical blank interrupt
+    vblank: bool, // set at 60 Hz, cleared when drawing (display wait quirk)
+
+    // Keypad
+    pressed_key: Option<u8>, // key pressed during Fx0A, stored once released (key release quirk)
+
+    random: Box<dyn RandomTrait>, // source of Cxkk
+
     // Configuration
     clock_rate: f32,
     ignore_unknown_instructions: bool,
     program_address: usize,
+    mode: Mode,
+    quirks: Quirks,
 }
 
 impl CpuTrait for Cpu {
@@ -80,43 +173,43 @@ impl CpuTrait for Cpu {
         &mut self,
         memory: &mut dyn MemoryTrait,
         rom_reader: &mut dyn Read,
-    ) -> Result<usize, Error> {
+    ) -> Result<usize, EmulatorError> {
         // Clear memory
         memory.clear();
 
-        // Copy font to memory at 0x000
-        if FONT_BYTES > memory.get_size() {
-            panic!("Font size ({font_bytes} bytes) is larger than available memory ({memory_size} bytes)",
-                font_bytes = FONT_BYTES, memory_size = memory.get_size());
+        // Copy fonts to memory at 0x000
+        if FONT_BYTES + LARGE_FONT_BYTES > memory.get_size() {
+            return Err(EmulatorError::FontTooLarge {
+                font_size: FONT_BYTES + LARGE_FONT_BYTES,
+                memory_size: memory.get_size(),
+            });
         }
 
         {
-            println!(
-                "Copying font ({font_bytes} bytes) to memory at 0x000",
-                font_bytes = FONT_BYTES
-            );
             let mut memory_stream = BufWriter::new(&mut memory.read_all()[0..FONT_BYTES]);
             memory_stream.write_all(FONT.as_ref()).unwrap();
         }
+        {
+            let mut memory_stream = BufWriter::new(
+                &mut memory.read_all()[LARGE_FONT_ADDRESS..(LARGE_FONT_ADDRESS + LARGE_FONT_BYTES)],
+            );
+            memory_stream.write_all(LARGE_FONT.as_ref()).unwrap();
+        }
 
         // Read ROM
-        println!("Reading ROM");
         let mut rom = Vec::new();
         rom_reader.read_to_end(&mut rom)?;
 
         // Copy ROM into memory
         if rom.len() < 2 {
-            panic!("ROM does not contain any instructions");
+            return Err(EmulatorError::EmptyRom);
         } else if rom.len() > memory.get_size() - self.program_address {
-            panic!("ROM size ({rom_size} bytes) is larger than available program memory ({available_memory}) bytes)",
-                   rom_size = rom.len(), available_memory = memory.get_size() - self.program_address);
+            return Err(EmulatorError::RomTooLarge {
+                rom_size: rom.len(),
+                available_memory: memory.get_size() - self.program_address,
+            });
         }
         {
-            println!(
-                "Copying ROM ({rom_size} bytes) to memory at 0x{program_start:X}",
-                rom_size = rom.len(),
-                program_start = self.program_address
-            );
             let mut memory_stream = BufWriter::new(
                 &mut memory.read_all()[self.program_address..(self.program_address + rom.len())],
             );
@@ -124,44 +217,60 @@ impl CpuTrait for Cpu {
         }
 
         self.pc = self.program_address;
+        self.exited = false;
         return Ok(rom.len());
     }
 
     fn step(
         &mut self,
-        delta_time: f32,
         memory: &mut dyn MemoryTrait,
         keypad: &mut dyn KeypadTrait,
         display: &mut dyn DisplayTrait,
         speaker: &mut dyn SpeakerTrait,
         debug_cpu: bool,
         debug_memory: bool,
-    ) {
+    ) -> Result<(), EmulatorError> {
+        if self.exited {
+            return Ok(());
+        }
+
         // Fetch opcode
+        self.check_address(memory, self.pc, 2)?;
         self.opcode = (memory.read(self.pc) as u16) << 8 | (memory.read(self.pc + 1) as u16);
 
         // Debugging
         if debug_cpu {
-            self.print_debug_info();
+            self.print_debug_info(memory);
         }
         if debug_memory {
             memory.print_debug_info();
         }
 
         // Execute opcode
-        self.execute_instruction(memory, keypad, display);
+        self.execute_instruction(memory, keypad, display, speaker)
+    }
 
-        // Periodic tasks
-        self.update_delay_timer(delta_time);
-        self.update_sound_timer(delta_time, speaker);
+    // Called once at the end of every 60 Hz frame
+    fn end_frame(&mut self, speaker: &mut dyn SpeakerTrait) {
+        self.vblank = true;
+        self.update_delay_timer();
+        self.update_sound_timer(speaker);
     }
 
     fn get_clock_rate(&self) -> f32 {
         self.clock_rate
     }
 
-    fn print_debug_info(&self) {
-        let opname = Cpu::get_opname(&self.opcode);
+    fn get_mode(&self) -> Mode {
+        self.mode
+    }
+
+    fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    fn print_debug_info(&self, memory: &dyn MemoryTrait) {
+        let opname = disassemble_at(memory, self.pc, self.mode);
         println!(
             "Op: 0x{:X} {}, PC: {}, I: 0x{:X}, DT: {}, ST: {}",
             self.opcode, opname, self.pc, self.i, self.delay_timer, self.sound_timer
@@ -172,13 +281,63 @@ impl CpuTrait for Cpu {
     }
 }
 
-impl Cpu {
-    pub fn new(clock_rate: f32, ignore_unknown_instructions: bool, program_address: usize) -> Cpu {
-        println!(
-            "Initializing processor with {clock_rate} Hz",
-            clock_rate = clock_rate
-        );
+impl CpuDebugTrait for Cpu {
+    fn get_pc(&self) -> usize {
+        self.pc
+    }
+
+    fn set_pc(&mut self, pc: usize) {
+        self.pc = pc;
+    }
+
+    fn get_register(&self, x: usize) -> u8 {
+        self.v[x]
+    }
+
+    fn set_register(&mut self, x: usize, value: u8) {
+        self.v[x] = value;
+    }
+
+    fn get_i(&self) -> u16 {
+        self.i
+    }
 
+    fn set_i(&mut self, i: u16) {
+        self.i = i;
+    }
+
+    fn get_delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
+    }
+
+    fn get_sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+    }
+
+    // Return addresses of the active subroutines, the innermost one is last
+    fn get_stack(&self) -> &[usize] {
+        &self.stack[1..(self.sp as usize + 1)]
+    }
+}
+
+impl Cpu {
+    /// Creates a processor, the registers are set when a ROM is loaded.
+    /// Unknown instructions are skipped instead of failing if ignore_unknown_instructions is set.
+    pub fn new(
+        clock_rate: f32,
+        ignore_unknown_instructions: bool,
+        program_address: usize,
+        mode: Mode,
+        quirks: Quirks,
+    ) -> Cpu {
         Cpu {
             // Program
             opcode: 0x00,
@@ -189,33 +348,150 @@ impl Cpu {
             i: 0,
 
             delay_timer: 0,
-            delay_timer_f: 0.0,
             sound_timer: 0,
-            sound_timer_f: 0.0,
 
             // Stack
             stack: [0; 16],
             sp: 0,
 
+            // SUPER-CHIP
+            rpl: [0; 16],
+            exited: false,
+
+            // Vertical blank interrupt
+            vblank: false,
+
+            // Keypad
+            pressed_key: None,
+
+            random: Box::new(Random::from_entropy()),
+
             // Configuration
             clock_rate: clock_rate,
             ignore_unknown_instructions: ignore_unknown_instructions,
             program_address: program_address,
+            mode: mode,
+            quirks: quirks,
         }
     }
 
+    /// Makes the values of Cxkk reproducible
+    pub fn set_seed(&mut self, seed: u64) {
+        self.set_random(Box::new(Random::new(seed)));
+    }
+
+    /// Replaces the random number generator of Cxkk
+    pub fn set_random(&mut self, random: Box<dyn RandomTrait>) {
+        self.random = random;
+    }
+
+    /// Writes the registers, timers and stack to a save state
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.mode as u8);
+        state.write_u16(self.opcode);
+        state.write_u32(self.pc as u32);
+
+        state.write_bytes(&self.v);
+        state.write_u16(self.i);
+        state.write_u8(self.delay_timer);
+        state.write_u8(self.sound_timer);
+
+        for address in self.stack.iter() {
+            state.write_u32(*address as u32);
+        }
+        state.write_u8(self.sp);
+
+        state.write_bytes(&self.rpl);
+        state.write_bool(self.exited);
+
+        state.write_bool(self.vblank);
+
+        state.write_bool(self.pressed_key.is_some());
+        state.write_u8(self.pressed_key.unwrap_or(0));
+
+        self.random.save_state(state);
+    }
+
+    /// Reads the state written by save_state, values outside of the memory are rejected
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), Error> {
+        if state.read_u8()? != self.mode as u8 {
+            return Err(invalid_data("Save state was created in a different mode"));
+        }
+        self.opcode = state.read_u16()?;
+        self.pc = state.read_u32()? as usize;
+
+        self.v.copy_from_slice(state.read_bytes(16)?);
+        self.i = state.read_u16()?;
+        self.delay_timer = state.read_u8()?;
+        self.sound_timer = state.read_u8()?;
+
+        for index in 0..self.stack.len() {
+            self.stack[index] = state.read_u32()? as usize;
+        }
+        self.sp = state.read_u8()?;
+
+        self.rpl.copy_from_slice(state.read_bytes(16)?);
+        self.exited = state.read_bool()?;
+
+        self.vblank = state.read_bool()?;
+
+        let waiting = state.read_bool()?;
+        let key = state.read_u8()?;
+        self.pressed_key = if waiting { Some(key) } else { None };
+
+        self.random.load_state(state)?;
+
+        // Values that would fault or panic on the next step, I needs no check as every access is
+        // checked against the memory size
+        let memory_size = match self.mode {
+            Mode::XoChip => XO_CHIP_MEMORY_SIZE,
+            _ => MEMORY_SIZE,
+        };
+        if self.pc >= memory_size {
+            return Err(invalid_data("Program counter is outside of memory"));
+        }
+        if self.sp as usize >= self.stack.len() {
+            return Err(invalid_data("Stack pointer is out of range"));
+        }
+        if self.stack.iter().any(|address| *address >= memory_size) {
+            return Err(invalid_data("Return address is outside of memory"));
+        }
+        if key >= 16 {
+            return Err(invalid_data("Key is out of range"));
+        }
+
+        Ok(())
+    }
+
     fn execute_instruction(
         &mut self,
         memory: &mut dyn MemoryTrait,
         keypad: &mut dyn KeypadTrait,
         display: &mut dyn DisplayTrait,
-    ) {
+        speaker: &mut dyn SpeakerTrait,
+    ) -> Result<(), EmulatorError> {
         let byte_1 = (self.opcode & 0xF000) >> 0xC;
         let byte_2 = ((self.opcode & 0x0F00) >> 0x8) as usize;
         let byte_3 = ((self.opcode & 0x00F0) >> 0x4) as usize;
         let byte_4 = self.opcode & 0x000F;
+        let super_chip = self.mode == Mode::SuperChip || self.mode == Mode::XoChip;
+        let xo_chip = self.mode == Mode::XoChip;
 
         match (byte_1, byte_2, byte_3, byte_4) {
+            (0x0, 0x0, 0xC, n) if super_chip => {
+                // 00Cn - SCD nibble; Scroll display n lines down. (SUPER-CHIP)
+
+                display.scroll_down(n as usize);
+
+                self.pc += 2;
+            }
+            (0x0, 0x0, 0xD, n) if xo_chip => {
+                // 00Dn - SCU nibble; Scroll display n lines up. (XO-CHIP)
+
+                display.scroll_up(n as usize);
+
+                self.pc += 2;
+            }
             (0x0, 0x0, 0xE, 0x0) => {
                 // 00E0 - CLS; Clear the display.
 
@@ -228,11 +504,50 @@ impl Cpu {
                 // The interpreter sets the program counter to the address at the top of the stack,
                 // then subtracts 1 from the stack pointer.
 
+                if self.sp == 0 {
+                    return Err(EmulatorError::StackUnderflow {
+                        opcode: self.opcode,
+                        pc: self.pc,
+                    });
+                }
                 self.pc = self.stack[self.sp as usize] as usize;
                 self.sp -= 1;
 
                 self.pc += 2;
             }
+            (0x0, 0x0, 0xF, 0xB) if super_chip => {
+                // 00FB - SCR; Scroll display 4 pixels right. (SUPER-CHIP)
+
+                display.scroll_right(4);
+
+                self.pc += 2;
+            }
+            (0x0, 0x0, 0xF, 0xC) if super_chip => {
+                // 00FC - SCL; Scroll display 4 pixels left. (SUPER-CHIP)
+
+                display.scroll_left(4);
+
+                self.pc += 2;
+            }
+            (0x0, 0x0, 0xF, 0xD) if super_chip => {
+                // 00FD - EXIT; Exit the interpreter. (SUPER-CHIP)
+
+                self.exited = true;
+            }
+            (0x0, 0x0, 0xF, 0xE) if super_chip => {
+                // 00FE - LOW; Disable high resolution graphics mode. (SUPER-CHIP)
+
+                display.set_high_resolution(false);
+
+                self.pc += 2;
+            }
+            (0x0, 0x0, 0xF, 0xF) if super_chip => {
+                // 00FF - HIGH; Enable high resolution (128x64) graphics mode. (SUPER-CHIP)
+
+                display.set_high_resolution(true);
+
+                self.pc += 2;
+            }
             (0x1, _, _, _) => {
                 // 1nnn - JP addr; Jump to location nnn.
                 // The interpreter sets the program counter to nnn.
@@ -244,6 +559,12 @@ impl Cpu {
                 // The interpreter increments the stack pointer, then puts the current PC on the top of the stack.
                 // The PC is then set to nnn.
 
+                if self.sp as usize + 1 >= self.stack.len() {
+                    return Err(EmulatorError::StackOverflow {
+                        opcode: self.opcode,
+                        pc: self.pc,
+                    });
+                }
                 self.sp += 1;
                 self.stack[self.sp as usize] = self.pc as usize;
                 self.pc = self.op_0nnn();
@@ -253,7 +574,7 @@ impl Cpu {
                 // The interpreter compares register Vx to kk, and if they are equal, increments the program counter by 2.
 
                 if self.v[x] == self.op_00kk() {
-                    self.pc += 2 * 2;
+                    self.skip_next_instruction(memory);
                 } else {
                     self.pc += 2;
                 }
@@ -263,7 +584,7 @@ impl Cpu {
                 // The interpreter compares register Vx to kk, and if they are not equal, increments the program counter by 2.
 
                 if self.v[x] != self.op_00kk() {
-                    self.pc += 2 * 2;
+                    self.skip_next_instruction(memory);
                 } else {
                     self.pc += 2;
                 }
@@ -273,11 +594,33 @@ impl Cpu {
                 // The interpreter compares register Vx to register Vy, and if they are equal, increments the program counter by 2.
 
                 if self.v[x] == self.v[y] {
-                    self.pc += 2 * 2;
+                    self.skip_next_instruction(memory);
                 } else {
                     self.pc += 2;
                 }
             }
+            (0x5, x, y, 0x2) if xo_chip => {
+                // 5xy2 - SAVE Vx, Vy; Store registers Vx through Vy in memory starting at location I. (XO-CHIP)
+                // The registers are stored in reverse order if x > y. I is not modified.
+
+                self.check_address(memory, self.i as usize, Cpu::get_register_range(x, y).len())?;
+                for (offset, index) in Cpu::get_register_range(x, y).into_iter().enumerate() {
+                    memory.write(self.i as usize + offset, self.v[index]);
+                }
+
+                self.pc += 2;
+            }
+            (0x5, x, y, 0x3) if xo_chip => {
+                // 5xy3 - LOAD Vx, Vy; Read registers Vx through Vy from memory starting at location I. (XO-CHIP)
+                // The registers are read in reverse order if x > y. I is not modified.
+
+                self.check_address(memory, self.i as usize, Cpu::get_register_range(x, y).len())?;
+                for (offset, index) in Cpu::get_register_range(x, y).into_iter().enumerate() {
+                    self.v[index] = memory.read(self.i as usize + offset);
+                }
+
+                self.pc += 2;
+            }
             (0x6, x, _, _) => {
                 // 6xkk - LD Vx, byte; Set Vx = kk.
                 // The interpreter puts the value kk into register Vx.
@@ -307,6 +650,9 @@ impl Cpu {
                 // Performs a bitwise OR on the values of Vx and Vy, then stores the result in Vx.
 
                 self.v[x] |= self.v[y];
+                if self.quirks.vf_reset {
+                    self.v[0xF] = 0;
+                }
 
                 self.pc += 2;
             }
@@ -315,6 +661,9 @@ impl Cpu {
                 // Performs a bitwise AND on the values of Vx and Vy, then stores the result in Vx.
 
                 self.v[x] &= self.v[y];
+                if self.quirks.vf_reset {
+                    self.v[0xF] = 0;
+                }
 
                 self.pc += 2;
             }
@@ -323,6 +672,9 @@ impl Cpu {
                 // Performs a bitwise exclusive OR on the values of Vx and Vy, then stores the result in Vx.
 
                 self.v[x] ^= self.v[y];
+                if self.quirks.vf_reset {
+                    self.v[0xF] = 0;
+                }
 
                 self.pc += 2;
             }
@@ -350,10 +702,14 @@ impl Cpu {
 
                 self.pc += 2;
             }
-            (0x8, x, _, 0x6) => {
+            (0x8, x, y, 0x6) => {
                 // 8xy6 - SHR Vx {, Vy}; Set Vx = Vx SHR 1.
                 // If the least-significant bit of Vx is 1, then VF is set to 1, otherwise 0. Then Vx is divided by 2.
+                // Without the shifting quirk, Vy is shifted instead of Vx and the result is stored in Vx.
 
+                if !self.quirks.shifting {
+                    self.v[x] = self.v[y];
+                }
                 self.v[0xF] = self.v[x] & 0x1;
                 self.v[x] >>= 1;
 
@@ -371,9 +727,13 @@ impl Cpu {
 
                 self.pc += 2;
             }
-            (0x8, x, _, 0xE) => {
+            (0x8, x, y, 0xE) => {
                 // 8xyE - SHL Vx {, Vy}; Set Vx = Vx SHL 1.
                 // If the most-significant bit of Vx is 1, then VF is set to 1, otherwise to 0. Then Vx is multiplied by 2.
+                // Without the shifting quirk, Vy is shifted instead of Vx and the result is stored in Vx.
+                if !self.quirks.shifting {
+                    self.v[x] = self.v[y];
+                }
                 self.v[0xF] = (self.v[x] >> 7) & 0x1;
                 self.v[x] <<= 1;
 
@@ -384,7 +744,7 @@ impl Cpu {
                 // The values of Vx and Vy are compared, and if they are not equal, the program counter is increased by 2.
 
                 if self.v[x] != self.v[y] {
-                    self.pc += 2 * 2;
+                    self.skip_next_instruction(memory);
                 } else {
                     self.pc += 2;
                 }
@@ -397,22 +757,41 @@ impl Cpu {
 
                 self.pc += 2;
             }
-            (0xB, _, _, _) => {
+            (0xB, x, _, _) => {
                 // Bnnn - JP V0, addr; Jump to location nnn + V0.
                 // The program counter is set to nnn plus the value of V0.
+                // With the jumping quirk, Vx is used instead of V0 (Bxnn).
 
-                self.pc = self.op_0nnn() + self.v[0x0] as usize;
+                let offset = if self.quirks.jumping {
+                    self.v[x]
+                } else {
+                    self.v[0x0]
+                };
+                self.pc = self.op_0nnn() + offset as usize;
             }
             (0xC, x, _, _) => {
                 // Cxkk - RND Vx, byte; Set Vx = random byte AND kk.
                 // The interpreter generates a random number from 0 to 255, which is then ANDed with the value kk.
                 // The results are stored in Vx.
 
-                // TODO: Use caching
-                // Init once: let mut rng = rand::thread_rng();
-                // Use: rng.gen::<u8>()
+                self.v[x] = self.op_00kk() & self.random.next_u8();
+
+                self.pc += 2;
+            }
+            (0xD, x, y, 0x0) if super_chip => {
+                // Dxy0 - DRW Vx, Vy, 0; Display 16x16 sprite starting at memory location I at (Vx, Vy), set VF = collision. (SUPER-CHIP)
+                // The sprite consists of 32 bytes, two bytes per row.
+
+                if !self.wait_for_vblank() {
+                    return Ok(());
+                }
 
-                self.v[x] = self.op_00kk() & rand::random::<u8>();
+                let start = self.i as usize;
+                let end = self.i as usize + 32 * self.get_sprite_planes(display);
+                self.check_address(memory, start, end - start)?;
+                let sprite: Vec<u8> = (start..end).map(|address| memory.read(address)).collect();
+                self.v[0xF] =
+                    display.draw_large_sprite(self.v[x] as usize, self.v[y] as usize, &sprite);
 
                 self.pc += 2;
             }
@@ -422,25 +801,27 @@ impl Cpu {
                 // These bytes are then displayed as sprites on screen at coordinates (Vx, Vy).
                 // Sprites are XORed onto the existing screen. If this causes any pixels to be erased, VF is set to 1,
                 // otherwise it is set to 0. If the sprite is positioned so part of it is outside the coordinates of the display,
-                // it wraps around to the opposite side of the screen.
+                // it wraps around to the opposite side of the screen (unless the clipping quirk is enabled).
+
+                if !self.wait_for_vblank() {
+                    return Ok(());
+                }
 
                 let start = self.i as usize;
-                let end = self.i as usize + n as usize;
-                self.v[0xF] = display.draw_sprite(
-                    self.v[x] as usize,
-                    self.v[y] as usize,
-                    &memory.read_all()[start..end],
-                );
+                let end = self.i as usize + n as usize * self.get_sprite_planes(display);
+                self.check_address(memory, start, end - start)?;
+                let sprite: Vec<u8> = (start..end).map(|address| memory.read(address)).collect();
+                self.v[0xF] = display.draw_sprite(self.v[x] as usize, self.v[y] as usize, &sprite);
 
                 self.pc += 2;
             }
             (0xE, x, 0x9, 0xE) => {
                 // Ex9E - SKP Vx; Skip next instruction if key with the value of Vx is pressed.
                 // Checks the keyboard, and if the key corresponding to the value of Vx is currently in the down position,
-                // PC is increased by 2.
+                // PC is increased by 2. Only the lower 4 bits of Vx select the key.
 
-                if keypad.get_key(x as u8) {
-                    self.pc += 2 * 2;
+                if keypad.get_key(self.v[x] & 0xF) {
+                    self.skip_next_instruction(memory);
                 } else {
                     self.pc += 2;
                 }
@@ -448,14 +829,42 @@ impl Cpu {
             (0xE, x, 0xA, 0x1) => {
                 // ExA1 - SKNP Vx; Skip next instruction if key with the value of Vx is not pressed.
                 // Checks the keyboard, and if the key corresponding to the value of Vx is currently in the up position,
-                // PC is increased by 2.
+                // PC is increased by 2. Only the lower 4 bits of Vx select the key.
 
-                if !keypad.get_key(x as u8) {
-                    self.pc += 2 * 2;
+                if !keypad.get_key(self.v[x] & 0xF) {
+                    self.skip_next_instruction(memory);
                 } else {
                     self.pc += 2;
                 }
             }
+            (0xF, 0x0, 0x0, 0x0) if xo_chip => {
+                // F000 nnnn - LD I, long addr; Set I = nnnn. (XO-CHIP)
+                // The 16-bit address is stored in the two bytes following the instruction.
+
+                self.check_address(memory, self.pc + 2, 2)?;
+                self.i = (memory.read(self.pc + 2) as u16) << 8 | memory.read(self.pc + 3) as u16;
+
+                self.pc += 2 * 2;
+            }
+            (0xF, n, 0x0, 0x1) if xo_chip => {
+                // Fn01 - PLANE n; Select the bit-planes n for drawing, scrolling and clearing. (XO-CHIP)
+
+                display.select_planes(n as u8);
+
+                self.pc += 2;
+            }
+            (0xF, 0x0, 0x0, 0x2) if xo_chip => {
+                // F002 - AUDIO; Load the 16-byte audio pattern buffer from memory starting at location I. (XO-CHIP)
+
+                self.check_address(memory, self.i as usize, AUDIO_PATTERN_BYTES)?;
+                let mut pattern = [0; AUDIO_PATTERN_BYTES];
+                for (index, sample) in pattern.iter_mut().enumerate() {
+                    *sample = memory.read(self.i as usize + index);
+                }
+                speaker.set_pattern(Some(pattern));
+
+                self.pc += 2;
+            }
             (0xF, x, 0x0, 0x7) => {
                 // Fx07 - LD Vx, DT; Set Vx = delay timer value.
                 // The value of DT is placed into Vx.
@@ -467,13 +876,26 @@ impl Cpu {
             (0xF, x, 0x0, 0xA) => {
                 // Fx0A - LD Vx, K; Wait for a key press, store the value of the key in Vx.
                 // All execution stops until a key is pressed, then the value of that key is stored in Vx.
+                // With the key release quirk, the key is only stored once it is released again.
 
-                for index in 0x0..0xF {
-                    if keypad.get_key(index) {
-                        self.v[x] = index;
+                match self.pressed_key {
+                    Some(key) if !keypad.get_key(key) => {
+                        self.v[x] = key;
+                        self.pressed_key = None;
 
                         self.pc += 2;
-                        break;
+                    }
+                    Some(_) => (),
+                    None => {
+                        if let Some(key) = (0x0..=0xF).find(|key| keypad.get_key(*key)) {
+                            if self.quirks.key_release {
+                                self.pressed_key = Some(key);
+                            } else {
+                                self.v[x] = key;
+
+                                self.pc += 2;
+                            }
+                        }
                     }
                 }
             }
@@ -482,7 +904,6 @@ impl Cpu {
                 // DT is set equal to the value of Vx.
 
                 self.delay_timer = self.v[x];
-                self.delay_timer_f = self.delay_timer as f32;
 
                 self.pc += 2;
             }
@@ -491,14 +912,14 @@ impl Cpu {
                 // ST is set equal to the value of Vx.
 
                 self.sound_timer = self.v[x];
-                self.sound_timer_f = self.sound_timer as f32;
+                speaker.set_tone(self.sound_timer > 0);
 
                 self.pc += 2;
             }
             (0xF, x, 0x1, 0xE) => {
                 // Fx1E - ADD I, Vx; Set I = I + Vx.
                 // The values of I and Vx are added, and the results are stored in I.
-                self.i += self.v[x] as u16;
+                self.i = self.i.wrapping_add(self.v[x] as u16);
 
                 self.pc += 2;
             }
@@ -509,10 +930,26 @@ impl Cpu {
 
                 self.pc += 2;
             }
+            (0xF, x, 0x3, 0x0) if super_chip => {
+                // Fx30 - LD HF, Vx; Set I = location of large sprite for digit Vx. (SUPER-CHIP)
+                // The value of I is set to the location for the 8x10 hexadecimal sprite corresponding to the value of Vx.
+
+                self.i = (LARGE_FONT_ADDRESS + self.v[x] as usize * LARGE_FONT_WIDTH) as u16;
+
+                self.pc += 2;
+            }
+            (0xF, x, 0x3, 0xA) if xo_chip => {
+                // Fx3A - PITCH Vx; Set the audio pattern playback rate to 4000*2^((Vx-64)/48) Hz. (XO-CHIP)
+
+                speaker.set_pitch(self.v[x]);
+
+                self.pc += 2;
+            }
             (0xF, x, 0x3, 0x3) => {
                 // Fx33 - LD B, Vx; Store BCD representation of Vx in memory locations I, I+1, and I+2.
                 // The interpreter takes the decimal value of Vx, and places the hundreds digit in memory at location in I,
                 // the tens digit at location I+1, and the ones digit at location I+2.
+                self.check_address(memory, self.i as usize, 3)?;
                 memory.write(self.i as usize, self.v[x] / 100);
                 memory.write(self.i as usize + 1, (self.v[x] / 10) % 10);
                 memory.write(self.i as usize + 2, self.v[x] % 10);
@@ -522,22 +959,44 @@ impl Cpu {
             (0xF, x, 0x5, 0x5) => {
                 // Fx55 - LD [I], Vx; Store registers V0 through Vx in memory starting at location I.
                 // The interpreter copies the values of registers V0 through Vx into memory, starting at the address in I.
+                // With the memory increment quirk, I is incremented by x + 1 afterwards.
 
+                self.check_address(memory, self.i as usize, x + 1)?;
                 for index in 0..(x + 1) {
                     memory.write(self.i as usize + index, self.v[index] as u8);
                 }
-                self.i += x as u16 + 1;
+                if self.quirks.memory_increment {
+                    self.i = self.i.wrapping_add(x as u16 + 1);
+                }
 
                 self.pc += 2;
             }
             (0xF, x, 0x6, 0x5) => {
                 // Fx65 - LD Vx, [I]; Read registers V0 through Vx from memory starting at location I.
                 // The interpreter reads values from memory starting at location I into registers V0 through Vx.
+                // With the memory increment quirk, I is incremented by x + 1 afterwards.
 
+                self.check_address(memory, self.i as usize, x + 1)?;
                 for index in 0..(x + 1) {
                     self.v[index] = memory.read(self.i as usize + index) as u8;
                 }
-                self.i += x as u16 + 1;
+                if self.quirks.memory_increment {
+                    self.i = self.i.wrapping_add(x as u16 + 1);
+                }
+
+                self.pc += 2;
+            }
+            (0xF, x, 0x7, 0x5) if super_chip => {
+                // Fx75 - LD R, Vx; Store registers V0 through Vx in the RPL user flags. (SUPER-CHIP)
+
+                self.rpl[0..(x + 1)].copy_from_slice(&self.v[0..(x + 1)]);
+
+                self.pc += 2;
+            }
+            (0xF, x, 0x8, 0x5) if super_chip => {
+                // Fx85 - LD Vx, R; Read registers V0 through Vx from the RPL user flags. (SUPER-CHIP)
+
+                self.v[0..(x + 1)].copy_from_slice(&self.rpl[0..(x + 1)]);
 
                 self.pc += 2;
             }
@@ -547,91 +1006,98 @@ impl Cpu {
                     println!("instruction not implemented. opcode: {opcode}, program counter: {program_counter}",
                              opcode = self.opcode, program_counter = self.pc);
                 } else {
-                    panic!("instruction not implemented. opcode: {opcode}, program counter: {program_counter}",
-                           opcode = self.opcode, program_counter = self.pc);
+                    return Err(EmulatorError::UnknownInstruction {
+                        opcode: self.opcode,
+                        pc: self.pc,
+                    });
                 }
             }
         }
-    }
 
-    fn update_delay_timer(&mut self, delta_time: f32) {
-        if self.delay_timer_f > 0.0 {
-            self.delay_timer_f -= delta_time / 1000.0 / (1.0 / 60.0);
-            if self.delay_timer_f < 0.0 {
-                self.delay_timer_f = 0.0;
-            }
+        Ok(())
+    }
 
-            self.delay_timer = self.delay_timer_f.floor() as u8;
+    // Returns false if drawing has to wait for the next vertical blank (display wait quirk)
+    fn wait_for_vblank(&mut self) -> bool {
+        if self.quirks.display_wait && !self.vblank {
+            return false;
         }
+
+        self.vblank = false;
+        true
     }
 
-    fn update_sound_timer(&mut self, delta_time: f32, speaker: &mut dyn SpeakerTrait) {
-        if self.sound_timer_f > 0.0 {
-            self.sound_timer_f -= delta_time / 1000.0 / (1.0 / 60.0);
-            if self.sound_timer_f < 0.0 {
-                self.sound_timer_f = 0.0;
-            }
+    fn update_delay_timer(&mut self) {
+        if self.delay_timer > 0 {
+            self.delay_timer -= 1;
+        }
+    }
 
-            self.sound_timer = self.sound_timer_f.ceil() as u8;
+    fn update_sound_timer(&mut self, speaker: &mut dyn SpeakerTrait) {
+        if self.sound_timer > 0 {
+            self.sound_timer -= 1;
             if self.sound_timer == 0 {
-                speaker.queue_beep();
+                speaker.set_tone(false);
             }
         }
     }
 
-    fn op_00kk(&mut self) -> u8 {
-        (self.opcode & 0x00FF) as u8
+    // Faults if any of the length bytes starting at the address is outside of memory
+    fn check_address(
+        &self,
+        memory: &dyn MemoryTrait,
+        address: usize,
+        length: usize,
+    ) -> Result<(), EmulatorError> {
+        if address + length > memory.get_size() {
+            return Err(EmulatorError::AddressOutOfRange {
+                address: address.max(memory.get_size()),
+                pc: self.pc,
+            });
+        }
+
+        Ok(())
     }
 
-    fn op_0nnn(&mut self) -> usize {
-        (self.opcode & 0x0FFF) as usize
+    fn skip_next_instruction(&mut self, memory: &dyn MemoryTrait) {
+        // XO-CHIP: F000 nnnn is the only instruction that is four bytes long.
+        // A skip past the end of memory faults when the next instruction is fetched.
+        let next_opcode = if self.pc + 4 <= memory.get_size() {
+            (memory.read(self.pc + 2) as u16) << 8 | (memory.read(self.pc + 3) as u16)
+        } else {
+            0x0000
+        };
+        if self.mode == Mode::XoChip && next_opcode == 0xF000 {
+            self.pc += 2 * 3;
+        } else {
+            self.pc += 2 * 2;
+        }
     }
 
-    fn get_opname(opcode: &u16) -> &str {
-        let byte_1 = (opcode & 0xF000) >> 0xC;
-        let byte_2 = ((opcode & 0x0F00) >> 0x8) as usize;
-        let byte_3 = ((opcode & 0x00F0) >> 0x4) as usize;
-        let byte_4 = opcode & 0x000F;
+    // Number of planes a sprite is drawn to, each plane reads its own sprite data
+    fn get_sprite_planes(&self, display: &dyn DisplayTrait) -> usize {
+        if self.mode == Mode::XoChip {
+            display.get_selected_planes().count_ones() as usize
+        } else {
+            1
+        }
+    }
 
-        match (byte_1, byte_2, byte_3, byte_4) {
-            (0x0, 0x0, 0xE, 0x0) => "CLS",
-            (0x0, 0x0, 0xE, 0xE) => "RET",
-            (0x0, _, _, _) => "SYS",
-            (0x1, _, _, _) => "JP (addr)",
-            (0x2, _, _, _) => "CALL (addr)",
-            (0x3, _, _, _) => "SE (Vx, byte)",
-            (0x4, _, _, _) => "SNE (Vx, byte)",
-            (0x5, _, _, 0x0) => "SE (Vx, Vy)",
-            (0x6, _, _, _) => "LD (Vx, byte)",
-            (0x7, _, _, _) => "ADD (Vx, byte)",
-            (0x8, _, _, 0x0) => "LD (Vx, Vy)",
-            (0x8, _, _, 0x1) => "OR (Vx, Vy)",
-            (0x8, _, _, 0x2) => "AND (Vx, Vy)",
-            (0x8, _, _, 0x3) => "XOR (Vx, Vy)",
-            (0x8, _, _, 0x4) => "ADD (Vx, Vy)",
-            (0x8, _, _, 0x5) => "SUB (Vx, Vy)",
-            (0x8, _, _, 0x6) => "SHR (Vx, Vy)",
-            (0x8, _, _, 0x7) => "SUBN (Vy, Vy)",
-            (0x8, _, _, 0xE) => "SHL (Vx, Vy)",
-            (0x9, _, _, 0x0) => "SNE (Vy, Vy)",
-            (0xA, _, _, _) => "LD (I, addr)",
-            (0xB, _, _, _) => "JP (V0, addr)",
-            (0xC, _, _, _) => "RND (Vy, byte)",
-            (0xD, _, _, _) => "DRW (Vx, Vy, nibble)",
-            (0xE, _, 0x9, 0xE) => "SKP (Vx)",
-            (0xE, _, 0xA, 0x1) => "SKNP (Vx)",
-            (0xF, _, 0x0, 0x7) => "LD (Vx, DT)",
-            (0xF, _, 0x0, 0xA) => "LD (Vx, K)",
-            (0xF, _, 0x1, 0x5) => "LD (DT, Vx)",
-            (0xF, _, 0x1, 0x8) => "LD (ST, Vx)",
-            (0xF, _, 0x1, 0xE) => "ADD (I, Vx)",
-            (0xF, _, 0x2, 0x9) => "LD (F, Vx)",
-            (0xF, _, 0x3, 0x3) => "LD (B, Vx)",
-            (0xF, _, 0x5, 0x5) => "LD (I, Vx)",
-            (0xF, _, 0x6, 0x5) => "LD (Vx, I)",
-            _ => "?",
+    fn get_register_range(x: usize, y: usize) -> Vec<usize> {
+        if x <= y {
+            (x..(y + 1)).collect()
+        } else {
+            (y..(x + 1)).rev().collect()
         }
     }
+
+    fn op_00kk(&mut self) -> u8 {
+        (self.opcode & 0x00FF) as u8
+    }
+
+    fn op_0nnn(&mut self) -> usize {
+        (self.opcode & 0x0FFF) as usize
+    }
 }
 
 // TODO: Split into separate file
@@ -643,11 +1109,15 @@ mod tests {
     // - https://github.com/starrhorne/chip8-rust/blob/master/src/processor_test.rs (accessed 2020-04-21)
     // - https://github.com/ismaelrh/Java-chip8-emulator/blob/master/src/test/java/chip8/ProcessingUnitTest.java (accessed 2020-04-21)
     use super::*;
+    use crate::assembler::assemble;
     use crate::display::*;
+    use crate::error::EmulatorError;
     use crate::keypad::*;
     use crate::memory::*;
+    use crate::random::*;
     use crate::speaker::*;
-    use sdl2::keyboard::Keycode;
+    use mockall::predicate::eq;
+    use std::path::Path;
 
     const PROGRAM_START_ADDRESS: usize = 0x200;
 
@@ -661,18 +1131,71 @@ mod tests {
             instructions_bytes.extend(instruction.to_be_bytes().to_vec().into_iter());
         }
 
-        let mut cpu = Cpu::new(600.0, false, PROGRAM_START_ADDRESS);
+        let mut cpu = Cpu::new(
+            600.0,
+            false,
+            PROGRAM_START_ADDRESS,
+            Mode::Chip8,
+            Quirks::default(),
+        );
         cpu.load_rom(memory, &mut std::io::Cursor::new(instructions_bytes))
             .unwrap();
         cpu
     }
 
+    fn instantiate_cpu_with_source(memory: &mut dyn MemoryTrait, source: &str) -> Cpu {
+        let rom = assemble(source, Path::new("test.asm"), PROGRAM_START_ADDRESS).unwrap();
+
+        let mut cpu = instantiate_cpu(memory);
+        cpu.load_rom(memory, &mut std::io::Cursor::new(rom))
+            .unwrap();
+        cpu
+    }
+
+    fn run_steps(cpu: &mut Cpu, memory: &mut dyn MemoryTrait, steps: usize) {
+        let mut keypad = MockKeypadTrait::new();
+        let mut display = MockDisplayTrait::new();
+        let mut speaker = MockSpeakerTrait::new();
+
+        for _ in 0..steps {
+            cpu.step(
+                memory,
+                &mut keypad,
+                &mut display,
+                &mut speaker,
+                false,
+                false,
+            )
+            .unwrap();
+        }
+    }
+
+    fn instantiate_super_chip_cpu(memory: &mut dyn MemoryTrait) -> Cpu {
+        let mut cpu = instantiate_cpu(memory);
+        cpu.mode = Mode::SuperChip;
+        cpu
+    }
+
+    fn instantiate_xo_chip_cpu(memory: &mut dyn MemoryTrait) -> Cpu {
+        let mut cpu = instantiate_cpu(memory);
+        cpu.mode = Mode::XoChip;
+        cpu
+    }
+
     fn instantiate_memory() -> Memory {
-        Memory::new() // Not mocked dued to simplicity
+        Memory::new(MEMORY_SIZE) // Not mocked dued to simplicity
+    }
+
+    fn instantiate_xo_chip_memory() -> Memory {
+        Memory::new(XO_CHIP_MEMORY_SIZE) // Not mocked dued to simplicity
     }
 
     fn instantiate_display() -> Display {
-        Display::new(1) // Not mocked dued to simplicity
+        Display::new(Quirks::default()) // Not mocked dued to simplicity
+    }
+
+    fn instantiate_display_with_quirks(quirks: Quirks) -> Display {
+        Display::new(quirks) // Not mocked dued to simplicity
     }
 
     fn instantiate_keypad() -> Keypad {
@@ -682,9 +1205,11 @@ mod tests {
     fn execute_instruction(cpu: &mut Cpu, memory: &mut dyn MemoryTrait, opcode: u16) {
         let mut keypad = MockKeypadTrait::new();
         let mut display = MockDisplayTrait::new();
+        let mut speaker = MockSpeakerTrait::new();
 
         cpu.opcode = opcode;
-        cpu.execute_instruction(memory, &mut keypad, &mut display);
+        cpu.execute_instruction(memory, &mut keypad, &mut display, &mut speaker)
+            .unwrap();
     }
 
     fn execute_instruction_with_display(
@@ -694,9 +1219,11 @@ mod tests {
         opcode: u16,
     ) {
         let mut keypad = MockKeypadTrait::new();
+        let mut speaker = MockSpeakerTrait::new();
 
         cpu.opcode = opcode;
-        cpu.execute_instruction(memory, &mut keypad, display);
+        cpu.execute_instruction(memory, &mut keypad, display, &mut speaker)
+            .unwrap();
     }
 
     fn execute_instruction_with_keypad(
@@ -706,9 +1233,25 @@ mod tests {
         opcode: u16,
     ) {
         let mut display = MockDisplayTrait::new();
+        let mut speaker = MockSpeakerTrait::new();
 
         cpu.opcode = opcode;
-        cpu.execute_instruction(memory, keypad, &mut display);
+        cpu.execute_instruction(memory, keypad, &mut display, &mut speaker)
+            .unwrap();
+    }
+
+    fn execute_instruction_with_speaker(
+        cpu: &mut Cpu,
+        memory: &mut dyn MemoryTrait,
+        speaker: &mut dyn SpeakerTrait,
+        opcode: u16,
+    ) {
+        let mut keypad = MockKeypadTrait::new();
+        let mut display = MockDisplayTrait::new();
+
+        cpu.opcode = opcode;
+        cpu.execute_instruction(memory, &mut keypad, &mut display, speaker)
+            .unwrap();
     }
 
     fn test_math(v1: u8, v2: u8, operation: u16, expected_result: u8, expected_vf: u8) {
@@ -742,6 +1285,12 @@ mod tests {
             memory.read_all()[FONT.len() - 5..FONT.len()],
             [0xF0, 0x80, 0xF0, 0x80, 0x80]
         );
+
+        // First char in large font: 0
+        assert_eq!(
+            memory.read_all()[LARGE_FONT_ADDRESS..LARGE_FONT_ADDRESS + 10],
+            [0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C]
+        );
     }
 
     #[test]
@@ -756,21 +1305,214 @@ mod tests {
         assert_eq!(memory.read(PROGRAM_START_ADDRESS + 0x4), 0x00);
     }
 
+    #[test]
+    fn test_program_from_source() {
+        let mut memory = instantiate_memory();
+        let mut cpu = instantiate_cpu_with_source(
+            &mut memory,
+            "
+            VALUE = 5
+                LD V0, VALUE
+                CALL double
+                LD V1, V0
+            loop:
+                JP loop
+            double:
+                LD V2, V0
+                ADD V0, V2
+                RET
+            ",
+        );
+
+        run_steps(&mut cpu, &mut memory, 7);
+
+        assert_eq!(cpu.v[0x1], 10);
+        assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 6);
+        assert_eq!(cpu.sp, 0);
+    }
+
+    #[test]
+    fn test_load_rom_errors() {
+        let mut memory = instantiate_memory();
+        let mut cpu = instantiate_cpu(&mut memory);
+
+        let result = cpu.load_rom(&mut memory, &mut std::io::Cursor::new(vec![0x12]));
+        assert!(matches!(result, Err(EmulatorError::EmptyRom)));
+
+        let rom = vec![0x00; MEMORY_SIZE - PROGRAM_START_ADDRESS + 1];
+        let result = cpu.load_rom(&mut memory, &mut std::io::Cursor::new(rom));
+        assert!(matches!(
+            result,
+            Err(EmulatorError::RomTooLarge {
+                rom_size: 3585,
+                available_memory: 3584
+            })
+        ));
+    }
+
+    #[test]
+    fn test_unknown_instruction_error() {
+        let mut memory = instantiate_memory();
+        let mut cpu = instantiate_cpu(&mut memory);
+        let mut keypad = MockKeypadTrait::new();
+        let mut display = MockDisplayTrait::new();
+        let mut speaker = MockSpeakerTrait::new();
+        cpu.opcode = 0x0000;
+
+        let result = cpu.execute_instruction(&mut memory, &mut keypad, &mut display, &mut speaker);
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Unknown instruction 0000 at 0x200"
+        );
+        assert_eq!(cpu.pc, PROGRAM_START_ADDRESS);
+    }
+
+    #[test]
+    fn test_address_out_of_range_errors() {
+        // LD I, 0xFFF; DRW V0, V0, 5
+        let mut memory = instantiate_memory();
+        let mut cpu = instantiate_cpu_with_program(&mut memory, vec![0xAFFF, 0xD005]);
+        let mut keypad = MockKeypadTrait::new();
+        let mut display = MockDisplayTrait::new();
+        display.expect_get_selected_planes().return_const(1u8);
+        let mut speaker = MockSpeakerTrait::new();
+
+        run_steps(&mut cpu, &mut memory, 1);
+        let result = cpu.step(
+            &mut memory,
+            &mut keypad,
+            &mut display,
+            &mut speaker,
+            false,
+            false,
+        );
+
+        assert!(matches!(
+            result,
+            Err(EmulatorError::AddressOutOfRange {
+                address: 0x1000,
+                pc: 0x202
+            })
+        ));
+        assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2);
+
+        // Fx33, Fx55 and Fx65 fault if they would access memory past the end
+        for opcode in [0xF033, 0xF255, 0xF265].iter() {
+            let mut cpu = instantiate_cpu(&mut memory);
+            cpu.i = 0xFFE;
+            cpu.opcode = *opcode;
+            let result =
+                cpu.execute_instruction(&mut memory, &mut keypad, &mut display, &mut speaker);
+            assert!(result.is_err(), "{:04X}", opcode);
+        }
+
+        // The program counter runs past the end of memory
+        let mut cpu = instantiate_cpu(&mut memory);
+        cpu.pc = MEMORY_SIZE - 1;
+        let result = cpu.step(
+            &mut memory,
+            &mut keypad,
+            &mut display,
+            &mut speaker,
+            false,
+            false,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_load_state_rejects_invalid_values() {
+        let mut memory = instantiate_memory();
+        let valid = instantiate_cpu(&mut memory);
+
+        let mut stack_pointer = instantiate_cpu(&mut memory);
+        stack_pointer.sp = 16;
+        let mut return_address = instantiate_cpu(&mut memory);
+        return_address.stack[3] = MEMORY_SIZE;
+        let mut pressed_key = instantiate_cpu(&mut memory);
+        pressed_key.pressed_key = Some(0x10);
+
+        for (cpu, valid) in [
+            (valid, true),
+            (stack_pointer, false),
+            (return_address, false),
+            (pressed_key, false),
+        ]
+        .iter()
+        {
+            let mut writer = StateWriter::new();
+            cpu.save_state(&mut writer);
+            let mut state = Vec::new();
+            writer.finish(&mut state).unwrap();
+
+            let mut restored = instantiate_cpu(&mut memory);
+            let result = restored.load_state(&mut StateReader::new(&mut state.as_slice()).unwrap());
+            assert_eq!(result.is_ok(), *valid);
+        }
+    }
+
+    #[test]
+    fn test_stack_errors() {
+        let mut memory = instantiate_memory();
+        let mut cpu = instantiate_cpu_with_source(&mut memory, "RET");
+        let mut keypad = MockKeypadTrait::new();
+        let mut display = MockDisplayTrait::new();
+        let mut speaker = MockSpeakerTrait::new();
+
+        let result = cpu.step(
+            &mut memory,
+            &mut keypad,
+            &mut display,
+            &mut speaker,
+            false,
+            false,
+        );
+        assert!(matches!(
+            result,
+            Err(EmulatorError::StackUnderflow {
+                opcode: 0x00EE,
+                pc: PROGRAM_START_ADDRESS
+            })
+        ));
+
+        let mut cpu = instantiate_cpu_with_source(&mut memory, "loop: CALL loop");
+        run_steps(&mut cpu, &mut memory, 15);
+        let result = cpu.step(
+            &mut memory,
+            &mut keypad,
+            &mut display,
+            &mut speaker,
+            false,
+            false,
+        );
+        assert!(matches!(
+            result,
+            Err(EmulatorError::StackOverflow {
+                opcode: 0x2200,
+                pc: PROGRAM_START_ADDRESS
+            })
+        ));
+        assert_eq!(cpu.sp, 15);
+    }
+
     #[test]
     fn test_timers() {
         let mut memory = instantiate_memory();
         let mut cpu = instantiate_cpu(&mut memory);
         let mut speaker = MockSpeakerTrait::new();
         cpu.delay_timer = 100;
-        cpu.delay_timer_f = 100.0;
         cpu.sound_timer = 200;
-        cpu.sound_timer_f = 200.0;
-
-        cpu.update_delay_timer((01.0 / 60.0) * 1000.0);
-        cpu.update_sound_timer((60.1 / 60.0) * 1000.0, &mut speaker);
 
+        cpu.end_frame(&mut speaker);
         assert_eq!(cpu.delay_timer, 99);
-        assert_eq!(cpu.sound_timer, 140);
+        assert_eq!(cpu.sound_timer, 199);
+
+        for _ in 0..60 {
+            cpu.end_frame(&mut speaker);
+        }
+        assert_eq!(cpu.delay_timer, 39);
+        assert_eq!(cpu.sound_timer, 139);
     }
 
     #[test]
@@ -778,16 +1520,17 @@ mod tests {
         let mut memory = instantiate_memory();
         let mut cpu = instantiate_cpu(&mut memory);
         let mut speaker = MockSpeakerTrait::new();
-        speaker.expect_queue_beep()
+        speaker
+            .expect_set_tone()
+            .with(eq(false))
             .times(1)
             .return_const(());
         cpu.delay_timer = 10;
-        cpu.delay_timer_f = 10.0;
         cpu.sound_timer = 10;
-        cpu.sound_timer_f = 10.0;
-        
-        cpu.update_delay_timer((60.0 / 60.0) * 1000.0);
-        cpu.update_sound_timer((60.0 / 60.0) * 1000.0, &mut speaker);
+
+        for _ in 0..60 {
+            cpu.end_frame(&mut speaker);
+        }
 
         assert_eq!(cpu.delay_timer, 0);
         assert_eq!(cpu.sound_timer, 0);
@@ -807,6 +1550,39 @@ mod tests {
         assert_eq!(cpu.stack[11], PROGRAM_START_ADDRESS);
     }
 
+    #[test]
+    fn test_op_00cn_scd() {
+        let mut memory = instantiate_memory();
+        let mut cpu = instantiate_super_chip_cpu(&mut memory);
+        let mut display = instantiate_display();
+        display.write_pixel(5, 0, true);
+        display.write_pixel(5, DISPLAY_HEIGHT - 2, true);
+
+        execute_instruction_with_display(&mut cpu, &mut memory, &mut display, 0x00C3);
+
+        assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2);
+        assert_eq!(display.read_pixel(5, 0), false);
+        assert_eq!(display.read_pixel(5, 3), true);
+        assert_eq!(display.read_pixel(5, DISPLAY_HEIGHT - 2), false);
+        assert_eq!(display.read_pixel(5, 1), false);
+    }
+
+    #[test]
+    fn test_op_00dn_scu() {
+        let mut memory = instantiate_xo_chip_memory();
+        let mut cpu = instantiate_xo_chip_cpu(&mut memory);
+        let mut display = instantiate_display();
+        display.write_pixel(5, 0, true);
+        display.write_pixel(5, DISPLAY_HEIGHT - 1, true);
+
+        execute_instruction_with_display(&mut cpu, &mut memory, &mut display, 0x00D3);
+
+        assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2);
+        assert_eq!(display.read_pixel(5, 0), false);
+        assert_eq!(display.read_pixel(5, DISPLAY_HEIGHT - 1), false);
+        assert_eq!(display.read_pixel(5, DISPLAY_HEIGHT - 4), true);
+    }
+
     #[test]
     fn test_op_00e0_cls() {
         let mut memory = instantiate_memory();
@@ -837,6 +1613,76 @@ mod tests {
         assert_eq!(cpu.sp, 9);
     }
 
+    #[test]
+    fn test_op_00fb_scr() {
+        let mut memory = instantiate_memory();
+        let mut cpu = instantiate_super_chip_cpu(&mut memory);
+        let mut display = instantiate_display();
+        display.write_pixel(0, 7, true);
+        display.write_pixel(DISPLAY_WIDTH - 2, 7, true);
+
+        execute_instruction_with_display(&mut cpu, &mut memory, &mut display, 0x00FB);
+
+        assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2);
+        assert_eq!(display.read_pixel(0, 7), false);
+        assert_eq!(display.read_pixel(4, 7), true);
+        assert_eq!(display.read_pixel(DISPLAY_WIDTH - 2, 7), false);
+        assert_eq!(display.read_pixel(2, 7), false);
+    }
+
+    #[test]
+    fn test_op_00fc_scl() {
+        let mut memory = instantiate_memory();
+        let mut cpu = instantiate_super_chip_cpu(&mut memory);
+        let mut display = instantiate_display();
+        display.write_pixel(2, 7, true);
+        display.write_pixel(DISPLAY_WIDTH - 1, 7, true);
+
+        execute_instruction_with_display(&mut cpu, &mut memory, &mut display, 0x00FC);
+
+        assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2);
+        assert_eq!(display.read_pixel(2, 7), false);
+        assert_eq!(display.read_pixel(DISPLAY_WIDTH - 1, 7), false);
+        assert_eq!(display.read_pixel(DISPLAY_WIDTH - 5, 7), true);
+    }
+
+    #[test]
+    fn test_op_00fd_exit() {
+        let mut memory = instantiate_memory();
+        let mut cpu = instantiate_super_chip_cpu(&mut memory);
+
+        execute_instruction(&mut cpu, &mut memory, 0x00FD);
+
+        assert_eq!(cpu.pc, PROGRAM_START_ADDRESS);
+        assert_eq!(cpu.has_exited(), true);
+    }
+
+    #[test]
+    fn test_op_00fe_low_and_00ff_high() {
+        let mut memory = instantiate_memory();
+        let mut cpu = instantiate_super_chip_cpu(&mut memory);
+        let mut display = instantiate_display();
+
+        execute_instruction_with_display(&mut cpu, &mut memory, &mut display, 0x00FF);
+        assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2);
+        assert_eq!(display.get_width(), HIRES_DISPLAY_WIDTH);
+        assert_eq!(display.get_height(), HIRES_DISPLAY_HEIGHT);
+
+        execute_instruction_with_display(&mut cpu, &mut memory, &mut display, 0x00FE);
+        assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2 * 2);
+        assert_eq!(display.get_width(), DISPLAY_WIDTH);
+        assert_eq!(display.get_height(), DISPLAY_HEIGHT);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_super_chip_instructions_disabled_in_chip_8_mode() {
+        let mut memory = instantiate_memory();
+        let mut cpu = instantiate_cpu(&mut memory);
+
+        execute_instruction(&mut cpu, &mut memory, 0x00FF);
+    }
+
     #[test]
     fn test_op_1nnn_jp() {
         let mut memory = instantiate_memory();
@@ -907,6 +1753,58 @@ mod tests {
         assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2 * 2);
     }
 
+    #[test]
+    fn test_op_5xy0_sevxvy_skips_long_instruction() {
+        let mut memory = instantiate_xo_chip_memory();
+        let mut cpu = instantiate_xo_chip_cpu(&mut memory);
+        memory.write(PROGRAM_START_ADDRESS + 2, 0xF0);
+        memory.write(PROGRAM_START_ADDRESS + 3, 0x00);
+        cpu.v[0xA] = 0x07;
+        cpu.v[0xB] = 0x07;
+
+        execute_instruction(&mut cpu, &mut memory, 0x5AB0);
+
+        assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2 * 3);
+    }
+
+    #[test]
+    fn test_op_5xy2_savevxvy() {
+        let mut memory = instantiate_xo_chip_memory();
+        let mut cpu = instantiate_xo_chip_cpu(&mut memory);
+        cpu.i = 1000;
+        cpu.v[0x2] = 0x1;
+        cpu.v[0x3] = 0x2;
+        cpu.v[0x4] = 0x3;
+
+        execute_instruction(&mut cpu, &mut memory, 0x5242);
+        assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2);
+        assert_eq!(cpu.i, 1000);
+        assert_eq!(memory.read_all()[1000..1003], [0x1, 0x2, 0x3]);
+
+        // Reverse order
+        execute_instruction(&mut cpu, &mut memory, 0x5422);
+        assert_eq!(memory.read_all()[1000..1003], [0x3, 0x2, 0x1]);
+    }
+
+    #[test]
+    fn test_op_5xy3_loadvxvy() {
+        let mut memory = instantiate_xo_chip_memory();
+        let mut cpu = instantiate_xo_chip_cpu(&mut memory);
+        cpu.i = 1000;
+        memory.write(1000, 0x1);
+        memory.write(1001, 0x2);
+        memory.write(1002, 0x3);
+
+        execute_instruction(&mut cpu, &mut memory, 0x5243);
+        assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2);
+        assert_eq!(cpu.i, 1000);
+        assert_eq!(cpu.v[0x2..0x5], [0x1, 0x2, 0x3]);
+
+        // Reverse order
+        execute_instruction(&mut cpu, &mut memory, 0x5423);
+        assert_eq!(cpu.v[0x2..0x5], [0x3, 0x2, 0x1]);
+    }
+
     #[test]
     fn test_op_6xkk_ldvx() {
         let mut memory = instantiate_memory();
@@ -1038,7 +1936,6 @@ mod tests {
 
     #[test]
     fn test_op_cxkk_randvx() {
-        // TODO: Add variant for non null random value
         let mut memory = instantiate_memory();
         let mut cpu = instantiate_cpu(&mut memory);
 
@@ -1048,6 +1945,36 @@ mod tests {
         assert_eq!(cpu.v[0xA], 0);
     }
 
+    #[test]
+    fn test_op_cxkk_randvx_masked() {
+        let mut memory = instantiate_memory();
+        let mut cpu = instantiate_cpu(&mut memory);
+        let mut random = MockRandomTrait::new();
+        random.expect_next_u8().times(1).return_const(0b1010_1010);
+        cpu.set_random(Box::new(random));
+
+        execute_instruction(&mut cpu, &mut memory, 0xcA0F);
+
+        assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2);
+        assert_eq!(cpu.v[0xA], 0b0000_1010);
+    }
+
+    #[test]
+    fn test_op_cxkk_randvx_seeded() {
+        let mut memory = instantiate_memory();
+        let mut cpu = instantiate_cpu(&mut memory);
+        let mut other_cpu = instantiate_cpu(&mut memory);
+        cpu.set_seed(1234);
+        other_cpu.set_seed(1234);
+
+        for _ in 0..8 {
+            execute_instruction(&mut cpu, &mut memory, 0xcAFF);
+            execute_instruction(&mut other_cpu, &mut memory, 0xcAFF);
+
+            assert_eq!(cpu.v[0xA], other_cpu.v[0xA]);
+        }
+    }
+
     #[test]
     fn test_op_dxyn_drwvxvyn() {
         let mut memory = instantiate_memory();
@@ -1193,54 +2120,195 @@ mod tests {
         assert_eq!(display.read_pixel(0xA + 7, 0), false);
     }
 
+    #[test]
+    fn test_op_dxy0_drwvxvy0() {
+        let mut memory = instantiate_memory();
+        let mut cpu = instantiate_super_chip_cpu(&mut memory);
+        cpu.v[0x8] = HIRES_DISPLAY_WIDTH as u8 - 8; // Set X position for drawing
+        cpu.v[0x9] = 0xB; // Set Y position for drawing
+        cpu.i = (PROGRAM_START_ADDRESS + 6) as u16;
+
+        // Sprite to draw (16x16, only the first two rows are set)
+        memory.write(PROGRAM_START_ADDRESS + 6, 0b10000000);
+        memory.write(PROGRAM_START_ADDRESS + 7, 0b00000001);
+        memory.write(PROGRAM_START_ADDRESS + 8, 0b01000000);
+        memory.write(PROGRAM_START_ADDRESS + 9, 0b10000000);
+
+        let mut display = instantiate_display();
+        display.set_high_resolution(true);
+        execute_instruction_with_display(&mut cpu, &mut memory, &mut display, 0xD890);
+
+        assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2);
+        assert_eq!(cpu.v[0xF], 0);
+
+        let x_position = HIRES_DISPLAY_WIDTH - 8;
+        assert_eq!(display.read_pixel(x_position + 0, 0xB + 0), true);
+        assert_eq!(display.read_pixel(x_position + 1, 0xB + 0), false);
+        assert_eq!(display.read_pixel(7, 0xB + 0), true); // Wrapped around
+        assert_eq!(display.read_pixel(x_position + 1, 0xB + 1), true);
+        assert_eq!(display.read_pixel(0, 0xB + 1), true); // Wrapped around
+        assert_eq!(display.read_pixel(x_position + 0, 0xB + 2), false);
+
+        // Drawing the same sprite again erases it
+        execute_instruction_with_display(&mut cpu, &mut memory, &mut display, 0xD890);
+        assert_eq!(cpu.v[0xF], 1);
+        assert_eq!(display.read_pixel(x_position + 0, 0xB + 0), false);
+    }
+
+    #[test]
+    fn test_op_dxyn_drwvxvyn_planes() {
+        let mut memory = instantiate_xo_chip_memory();
+        let mut cpu = instantiate_xo_chip_cpu(&mut memory);
+        cpu.v[0x8] = 0xA; // Set X position for drawing
+        cpu.v[0x9] = 0xB; // Set Y position for drawing
+        cpu.i = 0x2000;
+
+        // Sprites to draw, one per plane
+        memory.write(0x2000, 0b11000000);
+        memory.write(0x2001, 0b10100000);
+
+        let mut display = instantiate_display();
+        execute_instruction_with_display(&mut cpu, &mut memory, &mut display, 0xF301);
+        execute_instruction_with_display(&mut cpu, &mut memory, &mut display, 0xD891);
+
+        assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2 * 2);
+        assert_eq!(cpu.v[0xF], 0);
+        assert_eq!(display.read_pixel_planes(0xA + 0, 0xB), 0b11);
+        assert_eq!(display.read_pixel_planes(0xA + 1, 0xB), 0b01);
+        assert_eq!(display.read_pixel_planes(0xA + 2, 0xB), 0b10);
+        assert_eq!(display.read_pixel_planes(0xA + 3, 0xB), 0b00);
+
+        // Clearing only affects the selected planes
+        execute_instruction_with_display(&mut cpu, &mut memory, &mut display, 0xF201);
+        execute_instruction_with_display(&mut cpu, &mut memory, &mut display, 0x00E0);
+        assert_eq!(display.read_pixel_planes(0xA + 0, 0xB), 0b01);
+        assert_eq!(display.read_pixel_planes(0xA + 2, 0xB), 0b00);
+    }
+
     #[test]
     fn test_op_ex9e_skpvx() {
-        // Note: Keycode A is mapped to hex 0x7
+        // Note: Key A is mapped to hex 0x7
 
         let mut memory = instantiate_memory();
         let mut cpu = instantiate_cpu(&mut memory);
         let mut keypad = instantiate_keypad();
+        cpu.v[0x7] = 0x7;
         execute_instruction_with_keypad(&mut cpu, &mut memory, &mut keypad, 0xE79E);
         assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2 * 1);
-        keypad.key_down(Keycode::S);
+        keypad.host_key_down("S");
         execute_instruction_with_keypad(&mut cpu, &mut memory, &mut keypad, 0xE79E);
         assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2 * 1 + 2 * 1);
 
         let mut memory = instantiate_memory();
         let mut cpu = instantiate_cpu(&mut memory);
         let mut keypad = instantiate_keypad();
-        keypad.key_down(Keycode::A);
-        keypad.key_down(Keycode::X);
-        keypad.key_up(Keycode::S);
-        keypad.key_down(Keycode::A);
+        keypad.host_key_down("A");
+        keypad.host_key_down("X");
+        keypad.host_key_up("S");
+        keypad.host_key_down("A");
+        cpu.v[0x7] = 0x7;
         execute_instruction_with_keypad(&mut cpu, &mut memory, &mut keypad, 0xE79E);
         assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2 * 2);
     }
 
     #[test]
     fn test_op_exa1_sknpvx() {
-        // Note: Keycode A is mapped to hex 0x7
+        // Note: Key A is mapped to hex 0x7
 
         let mut memory = instantiate_memory();
         let mut cpu = instantiate_cpu(&mut memory);
         let mut keypad = instantiate_keypad();
+        cpu.v[0x7] = 0x7;
         execute_instruction_with_keypad(&mut cpu, &mut memory, &mut keypad, 0xE7A1);
         assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2 * 2);
-        keypad.key_down(Keycode::S);
+        keypad.host_key_down("S");
         execute_instruction_with_keypad(&mut cpu, &mut memory, &mut keypad, 0xE7A1);
         assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2 * 2 + 2 * 2);
 
         let mut memory = instantiate_memory();
         let mut cpu = instantiate_cpu(&mut memory);
         let mut keypad = instantiate_keypad();
-        keypad.key_down(Keycode::A);
-        keypad.key_down(Keycode::X);
-        keypad.key_up(Keycode::S);
-        keypad.key_down(Keycode::A);
+        keypad.host_key_down("A");
+        keypad.host_key_down("X");
+        keypad.host_key_up("S");
+        keypad.host_key_down("A");
+        cpu.v[0x7] = 0x7;
         execute_instruction_with_keypad(&mut cpu, &mut memory, &mut keypad, 0xE7A1);
         assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2 * 1);
     }
 
+    #[test]
+    fn test_op_ex9e_exa1_all_keys() {
+        // The key is selected by the value of Vx, not by x
+        for key in 0x0..=0xF {
+            let mut keypad = instantiate_keypad();
+            keypad.set_key(key, true);
+
+            for value in 0x0..=0xF {
+                let mut memory = instantiate_memory();
+                let mut cpu = instantiate_cpu(&mut memory);
+                cpu.v[0x3] = value;
+                execute_instruction_with_keypad(&mut cpu, &mut memory, &mut keypad, 0xE39E);
+                let skipped = cpu.pc == PROGRAM_START_ADDRESS + 2 * 2;
+                assert_eq!(skipped, value == key, "SKP key {} V3 {}", key, value);
+
+                let mut cpu = instantiate_cpu(&mut memory);
+                cpu.v[0x3] = value;
+                execute_instruction_with_keypad(&mut cpu, &mut memory, &mut keypad, 0xE3A1);
+                let skipped = cpu.pc == PROGRAM_START_ADDRESS + 2 * 2;
+                assert_eq!(skipped, value != key, "SKNP key {} V3 {}", key, value);
+            }
+        }
+
+        // Only the lower 4 bits of Vx are used
+        let mut memory = instantiate_memory();
+        let mut cpu = instantiate_cpu(&mut memory);
+        let mut keypad = MockKeypadTrait::new();
+        keypad
+            .expect_get_key()
+            .with(eq(0xA))
+            .times(1)
+            .return_const(true);
+        cpu.v[0x3] = 0xFA;
+        execute_instruction_with_keypad(&mut cpu, &mut memory, &mut keypad, 0xE39E);
+        assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2 * 2);
+    }
+
+    #[test]
+    fn test_op_f000_ldilong() {
+        let mut memory = instantiate_xo_chip_memory();
+        let mut cpu = instantiate_xo_chip_cpu(&mut memory);
+        memory.write(PROGRAM_START_ADDRESS + 2, 0xBE);
+        memory.write(PROGRAM_START_ADDRESS + 3, 0xEF);
+
+        execute_instruction(&mut cpu, &mut memory, 0xF000);
+
+        assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2 * 2);
+        assert_eq!(cpu.i, 0xBEEF);
+    }
+
+    #[test]
+    fn test_op_f002_audio() {
+        let mut memory = instantiate_xo_chip_memory();
+        let mut cpu = instantiate_xo_chip_cpu(&mut memory);
+        let mut speaker = MockSpeakerTrait::new();
+        let mut pattern = [0; AUDIO_PATTERN_BYTES];
+        for index in 0..AUDIO_PATTERN_BYTES {
+            pattern[index] = index as u8;
+            memory.write(0x2000 + index, index as u8);
+        }
+        speaker
+            .expect_set_pattern()
+            .with(eq(Some(pattern)))
+            .times(1)
+            .return_const(());
+        cpu.i = 0x2000;
+
+        execute_instruction_with_speaker(&mut cpu, &mut memory, &mut speaker, 0xF002);
+
+        assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2);
+    }
+
     #[test]
     fn test_op_fx07_ldvxdt() {
         let mut memory = instantiate_memory();
@@ -1255,7 +2323,7 @@ mod tests {
 
     #[test]
     fn test_op_fx0a_ldvxk() {
-        // Note: Keycode A is mapped to hex 0x7
+        // Note: Key A is mapped to hex 0x7
 
         let mut memory = instantiate_memory();
         let mut cpu = instantiate_cpu(&mut memory);
@@ -1267,18 +2335,33 @@ mod tests {
         assert_eq!(cpu.v[0x6], 0x0);
 
         // Unmapped keypress
-        keypad.key_down(Keycode::P);
+        keypad.host_key_down("P");
         execute_instruction_with_keypad(&mut cpu, &mut memory, &mut keypad, 0xF70A);
         assert_eq!(cpu.pc, PROGRAM_START_ADDRESS);
         assert_eq!(cpu.v[0x6], 0x0);
 
         // Mapped keypress
-        keypad.key_down(Keycode::A);
+        keypad.host_key_down("A");
         execute_instruction_with_keypad(&mut cpu, &mut memory, &mut keypad, 0xF60A);
         assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2);
         assert_eq!(cpu.v[0x6], 0x7);
     }
 
+    #[test]
+    fn test_op_fx0a_ldvxk_all_keys() {
+        for key in 0x0..=0xF {
+            let mut memory = instantiate_memory();
+            let mut cpu = instantiate_cpu(&mut memory);
+            let mut keypad = instantiate_keypad();
+            keypad.set_key(key, true);
+
+            execute_instruction_with_keypad(&mut cpu, &mut memory, &mut keypad, 0xF60A);
+
+            assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2, "key {}", key);
+            assert_eq!(cpu.v[0x6], key);
+        }
+    }
+
     #[test]
     fn test_op_fx15_lddtvx() {
         let mut memory = instantiate_memory();
@@ -1295,9 +2378,15 @@ mod tests {
     fn test_op_fx18_ldstvx() {
         let mut memory = instantiate_memory();
         let mut cpu = instantiate_cpu(&mut memory);
+        let mut speaker = MockSpeakerTrait::new();
+        speaker
+            .expect_set_tone()
+            .with(eq(true))
+            .times(1)
+            .return_const(());
         cpu.v[6] = 7;
 
-        execute_instruction(&mut cpu, &mut memory, 0xF618);
+        execute_instruction_with_speaker(&mut cpu, &mut memory, &mut speaker, 0xF618);
 
         assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2);
         assert_eq!(cpu.sound_timer, 7);
@@ -1317,11 +2406,8 @@ mod tests {
     }
 
     #[test]
-    #[ignore]
     fn test_op_fx1e_addivx_overflow() {
-        // TODO: Reenable test
-        // An overflow leads to a panic at the moment
-        // I'm not sure what the intended behaviour is
+        // I is a 16-bit register and wraps around
         let mut memory = instantiate_memory();
         let mut cpu = instantiate_cpu(&mut memory);
         cpu.v[6] = 0x01;
@@ -1345,6 +2431,36 @@ mod tests {
         assert_eq!(cpu.i, FONT_WIDTH as u16 * 9);
     }
 
+    #[test]
+    fn test_op_fx30_ldhfvx() {
+        let mut memory = instantiate_memory();
+        let mut cpu = instantiate_super_chip_cpu(&mut memory);
+        cpu.v[7] = 9;
+
+        execute_instruction(&mut cpu, &mut memory, 0xF730);
+
+        assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2);
+        assert_eq!(cpu.i as usize, LARGE_FONT_ADDRESS + LARGE_FONT_WIDTH * 9);
+        assert_eq!(memory.read(cpu.i as usize), 0x3C);
+    }
+
+    #[test]
+    fn test_op_fx3a_pitchvx() {
+        let mut memory = instantiate_xo_chip_memory();
+        let mut cpu = instantiate_xo_chip_cpu(&mut memory);
+        let mut speaker = MockSpeakerTrait::new();
+        speaker
+            .expect_set_pitch()
+            .with(eq(112))
+            .times(1)
+            .return_const(());
+        cpu.v[7] = 112;
+
+        execute_instruction_with_speaker(&mut cpu, &mut memory, &mut speaker, 0xF73A);
+
+        assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2);
+    }
+
     #[test]
     fn test_op_fx33_ldbbvx() {
         let mut memory = instantiate_memory();
@@ -1405,4 +2521,154 @@ mod tests {
             assert_eq!(memory.read(1000 + i as usize), cpu.v[i]);
         }
     }
+
+    #[test]
+    fn test_op_fx75_ldrvx_and_fx85_ldvxr() {
+        let mut memory = instantiate_memory();
+        let mut cpu = instantiate_super_chip_cpu(&mut memory);
+        cpu.v[0x0] = 0x1;
+        cpu.v[0x3] = 0x2;
+        cpu.v[0x4] = 0x3;
+
+        execute_instruction(&mut cpu, &mut memory, 0xF375);
+        assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2);
+        assert_eq!(cpu.rpl[0..5], [0x1, 0x0, 0x0, 0x2, 0x0]);
+
+        cpu.v = [0; 16];
+        execute_instruction(&mut cpu, &mut memory, 0xF385);
+        assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2 * 2);
+        assert_eq!(cpu.v[0..5], [0x1, 0x0, 0x0, 0x2, 0x0]);
+    }
+
+    #[test]
+    fn test_quirk_vf_reset() {
+        let mut memory = instantiate_memory();
+        let mut cpu = instantiate_cpu(&mut memory);
+        cpu.quirks.vf_reset = true;
+
+        for operation in 1..4 {
+            cpu.v[0x0] = 0x0F;
+            cpu.v[0x1] = 0xF0;
+            cpu.v[0xF] = 0x5;
+            execute_instruction(&mut cpu, &mut memory, 0x8010 + operation);
+
+            assert_eq!(cpu.v[0xF], 0);
+        }
+    }
+
+    #[test]
+    fn test_quirk_shifting_disabled() {
+        let mut memory = instantiate_memory();
+        let mut cpu = instantiate_cpu(&mut memory);
+        cpu.quirks.shifting = false;
+
+        cpu.v[0x0] = 0x0;
+        cpu.v[0x1] = 0x5;
+        execute_instruction(&mut cpu, &mut memory, 0x8016);
+        assert_eq!(cpu.v[0x0], 0x2);
+        assert_eq!(cpu.v[0x1], 0x5);
+        assert_eq!(cpu.v[0xF], 1);
+
+        cpu.v[0x0] = 0x0;
+        cpu.v[0x1] = 0b11000000;
+        execute_instruction(&mut cpu, &mut memory, 0x801E);
+        assert_eq!(cpu.v[0x0], 0b10000000);
+        assert_eq!(cpu.v[0x1], 0b11000000);
+        assert_eq!(cpu.v[0xF], 1);
+    }
+
+    #[test]
+    fn test_quirk_memory_increment_disabled() {
+        let mut memory = instantiate_memory();
+        let mut cpu = instantiate_cpu(&mut memory);
+        cpu.quirks.memory_increment = false;
+        cpu.i = 1000;
+
+        execute_instruction(&mut cpu, &mut memory, 0xF355);
+        assert_eq!(cpu.i, 1000);
+
+        execute_instruction(&mut cpu, &mut memory, 0xF365);
+        assert_eq!(cpu.i, 1000);
+    }
+
+    #[test]
+    fn test_quirk_jumping() {
+        let mut memory = instantiate_memory();
+        let mut cpu = instantiate_cpu(&mut memory);
+        cpu.quirks.jumping = true;
+        cpu.v[0x0] = 0x01;
+        cpu.v[0x2] = 0x07;
+
+        execute_instruction(&mut cpu, &mut memory, 0xB2AD);
+
+        assert_eq!(cpu.pc, 0x2AD + 0x07);
+    }
+
+    #[test]
+    fn test_quirk_key_release() {
+        for key in 0x0..=0xF {
+            let mut memory = instantiate_memory();
+            let mut cpu = instantiate_cpu(&mut memory);
+            cpu.quirks.key_release = true;
+            let mut keypad = instantiate_keypad();
+
+            // Waits while the key is held
+            keypad.set_key(key, true);
+            execute_instruction_with_keypad(&mut cpu, &mut memory, &mut keypad, 0xF60A);
+            execute_instruction_with_keypad(&mut cpu, &mut memory, &mut keypad, 0xF60A);
+            assert_eq!(cpu.pc, PROGRAM_START_ADDRESS);
+
+            // Completes once it is released, even if another key is pressed
+            keypad.set_key(key, false);
+            keypad.set_key((key + 1) % 16, true);
+            execute_instruction_with_keypad(&mut cpu, &mut memory, &mut keypad, 0xF60A);
+            assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2, "key {}", key);
+            assert_eq!(cpu.v[0x6], key);
+        }
+    }
+
+    #[test]
+    fn test_quirk_clipping() {
+        let mut memory = instantiate_memory();
+        let mut cpu = instantiate_cpu(&mut memory);
+        let x_position = DISPLAY_WIDTH - 4;
+        cpu.v[0x8] = x_position as u8; // Set X position for drawing
+        cpu.v[0x9] = DISPLAY_HEIGHT as u8 - 1; // Set Y position for drawing
+        cpu.i = (PROGRAM_START_ADDRESS + 6) as u16;
+
+        // Sprites to draw
+        memory.write(PROGRAM_START_ADDRESS + 6, 0b11111111);
+        memory.write(PROGRAM_START_ADDRESS + 7, 0b11111111);
+
+        let mut quirks = Quirks::default();
+        quirks.clipping = true;
+        let mut display = instantiate_display_with_quirks(quirks);
+        execute_instruction_with_display(&mut cpu, &mut memory, &mut display, 0xD892);
+
+        assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2);
+        assert_eq!(display.read_pixel(x_position + 3, DISPLAY_HEIGHT - 1), true);
+        assert_eq!(display.read_pixel(0, DISPLAY_HEIGHT - 1), false);
+        assert_eq!(display.read_pixel(x_position, 0), false);
+        assert_eq!(display.read_pixel(0, 0), false);
+    }
+
+    #[test]
+    fn test_quirk_display_wait() {
+        let mut memory = instantiate_memory();
+        let mut cpu = instantiate_cpu(&mut memory);
+        let mut display = instantiate_display();
+        cpu.quirks.display_wait = true;
+
+        // Waits for the vertical blank interrupt
+        execute_instruction_with_display(&mut cpu, &mut memory, &mut display, 0xD001);
+        assert_eq!(cpu.pc, PROGRAM_START_ADDRESS);
+
+        cpu.end_frame(&mut MockSpeakerTrait::new());
+        execute_instruction_with_display(&mut cpu, &mut memory, &mut display, 0xD001);
+        assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2);
+
+        // Only one sprite is drawn per frame
+        execute_instruction_with_display(&mut cpu, &mut memory, &mut display, 0xD001);
+        assert_eq!(cpu.pc, PROGRAM_START_ADDRESS + 2);
+    }
 }
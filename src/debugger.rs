@@ -0,0 +1,696 @@
+use std::io::{BufRead, Write};
+
+use crate::cpu::*;
+use crate::disassembler::disassemble_at;
+use crate::emulator::Emulator;
+use crate::memory::*;
+use crate::rewind::RewindBuffer;
+use crate::speaker::SpeakerTrait;
+
+const REGISTER_NAMES: [&str; 20] = [
+    "v0", "v1", "v2", "v3", "v4", "v5", "v6", "v7", "v8", "v9", "va", "vb", "vc", "vd", "ve", "vf",
+    "i", "pc", "dt", "st",
+];
+
+const HELP: &str = "Commands:
+  continue (c)                      Resume execution
+  step (s) [count]                  Execute one or more instructions
+  back [count]                      Undo one or more instructions
+  next (n)                          Execute one instruction, stepping over subroutine calls
+  finish (f)                        Run until the current subroutine returns
+  break (b) <address>               Break when the program counter reaches an address
+  break-if <register> <op> <value>  Break when a condition becomes true (op: == != < <= > >=)
+  watch (w) <address> [read|write]  Break when an address is accessed (default: read and write)
+  list (l)                          List breakpoints
+  delete (d) <number>               Delete a breakpoint
+  registers (r)                     Print registers and timers
+  set <register> <value>            Modify V0-VF, I, PC, DT or ST
+  stack                             Print the return addresses of the active subroutines
+  memory (m) <address> [length]     Print memory
+  poke <address> <byte...>          Modify memory
+  quit (q)                          Quit the emulator
+Numbers are decimal or hexadecimal with 0x prefix.";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Debug, PartialEq)]
+enum Breakpoint {
+    Address(usize),
+    Watch(usize, Option<MemoryAccess>), // None watches reads and writes
+    Condition(usize, Comparison, u16, bool), // register, comparison, value, condition was true
+}
+
+// Decides when execution stops again
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RunMode {
+    Paused,
+    Running,
+    Step(u64),              // remaining instructions
+    StepOver(usize, usize), // return address, stack depth
+    StepOut(usize),         // stack depth
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DebuggerAction {
+    Resume,
+    Quit,
+}
+
+// Step debugger that is controlled by commands on a prompt
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    run_mode: RunMode,
+    resumed: bool, // breakpoints are ignored for the first instruction after resuming
+}
+
+impl Debugger {
+    pub fn new(paused: bool) -> Debugger {
+        Debugger {
+            breakpoints: Vec::new(),
+            run_mode: if paused {
+                RunMode::Paused
+            } else {
+                RunMode::Running
+            },
+            resumed: false,
+        }
+    }
+
+    pub fn pause(&mut self) {
+        self.run_mode = RunMode::Paused;
+    }
+
+    // Called before every instruction, returns true if the prompt should be shown
+    pub fn should_break(&mut self, emulator: &mut Emulator, output: &mut dyn Write) -> bool {
+        let pc = emulator.cpu.get_pc();
+        let depth = emulator.cpu.get_stack().len();
+
+        let mut stop = match &mut self.run_mode {
+            RunMode::Paused => true,
+            RunMode::Running => false,
+            RunMode::Step(0) => true,
+            RunMode::Step(remaining) => {
+                *remaining -= 1;
+                false
+            }
+            RunMode::StepOver(address, step_depth) => pc == *address && depth == *step_depth,
+            RunMode::StepOut(step_depth) => depth < *step_depth,
+        };
+
+        let resumed = self.resumed;
+        self.resumed = false;
+        for (index, breakpoint) in self.breakpoints.iter_mut().enumerate() {
+            match breakpoint {
+                Breakpoint::Address(address) if *address == pc && !resumed => {
+                    writeln!(output, "Breakpoint {} reached", index).unwrap();
+                    stop = true;
+                }
+                Breakpoint::Condition(register, comparison, value, was_true) => {
+                    let is_true = compare(get_register(emulator, *register), *comparison, *value);
+                    if is_true && !*was_true {
+                        writeln!(output, "Breakpoint {} condition is true", index).unwrap();
+                        stop = true;
+                    }
+                    *was_true = is_true;
+                }
+                _ => (),
+            }
+        }
+
+        if emulator.cpu.has_exited() {
+            stop = true;
+        }
+        if stop {
+            self.run_mode = RunMode::Paused;
+        }
+        stop
+    }
+
+    // Called after every instruction, pauses if a watched address was accessed
+    pub fn check_watchpoints(&mut self, emulator: &mut Emulator, output: &mut dyn Write) {
+        for (address, access) in emulator.memory.take_accesses() {
+            for (index, breakpoint) in self.breakpoints.iter().enumerate() {
+                if let Breakpoint::Watch(watched, watched_access) = breakpoint {
+                    if *watched == address && watched_access.map_or(true, |kind| kind == access) {
+                        writeln!(
+                            output,
+                            "Watchpoint {} hit: {:?} of 0x{:03X}",
+                            index, access, address
+                        )
+                        .unwrap();
+                        self.run_mode = RunMode::Paused;
+                    }
+                }
+            }
+        }
+    }
+
+    // Reads commands until execution is resumed
+    pub fn prompt(
+        &mut self,
+        emulator: &mut Emulator,
+        mut rewind: Option<&mut RewindBuffer>, // None while stepping back is not possible
+        input: &mut dyn BufRead,
+        output: &mut dyn Write,
+    ) -> DebuggerAction {
+        print_instruction(emulator, output);
+
+        loop {
+            write!(output, "(debug) ").unwrap();
+            output.flush().unwrap();
+
+            let mut line = String::new();
+            if input.read_line(&mut line).unwrap_or(0) == 0 {
+                return DebuggerAction::Quit;
+            }
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            if tokens.is_empty() {
+                continue;
+            }
+
+            match self.execute_command(emulator, rewind.as_deref_mut(), &tokens, output) {
+                Ok(Some(action)) => {
+                    if action == DebuggerAction::Resume {
+                        self.resumed = true;
+                        emulator.memory.take_accesses();
+                    }
+                    return action;
+                }
+                Ok(None) => (),
+                Err(message) => writeln!(output, "{}", message).unwrap(),
+            }
+        }
+    }
+
+    fn execute_command(
+        &mut self,
+        emulator: &mut Emulator,
+        rewind: Option<&mut RewindBuffer>,
+        tokens: &[&str],
+        output: &mut dyn Write,
+    ) -> Result<Option<DebuggerAction>, String> {
+        match tokens {
+            ["continue"] | ["c"] => {
+                self.run_mode = RunMode::Running;
+                return Ok(Some(DebuggerAction::Resume));
+            }
+            ["step"] | ["s"] => {
+                self.run_mode = RunMode::Step(1);
+                return Ok(Some(DebuggerAction::Resume));
+            }
+            ["step", count] | ["s", count] => {
+                let count = parse_number(count)? as u64;
+                self.run_mode = RunMode::Step(count.max(1));
+                return Ok(Some(DebuggerAction::Resume));
+            }
+            ["back"] | ["back", _] => {
+                let count = match tokens {
+                    [_, count] => parse_number(count)? as u64,
+                    _ => 1,
+                };
+                let rewind = rewind.ok_or_else(|| {
+                    "Stepping back is not possible while a movie is active".to_string()
+                })?;
+                rewind.step_back(emulator, count.max(1))?;
+                print_instruction(emulator, output);
+            }
+            ["next"] | ["n"] => {
+                // Stepping outside of memory faults like a single step
+                let pc = emulator.cpu.get_pc();
+                let opcode = if pc + 1 < emulator.memory.get_size() {
+                    (emulator.memory.read(pc) as u16) << 8 | emulator.memory.read(pc + 1) as u16
+                } else {
+                    0x0000
+                };
+                self.run_mode = if opcode & 0xF000 == 0x2000 {
+                    RunMode::StepOver(pc + 2, emulator.cpu.get_stack().len())
+                } else {
+                    RunMode::Step(1)
+                };
+                return Ok(Some(DebuggerAction::Resume));
+            }
+            ["finish"] | ["f"] => {
+                let depth = emulator.cpu.get_stack().len();
+                if depth == 0 {
+                    return Err("Not inside a subroutine".to_string());
+                }
+                self.run_mode = RunMode::StepOut(depth);
+                return Ok(Some(DebuggerAction::Resume));
+            }
+            ["quit"] | ["q"] => return Ok(Some(DebuggerAction::Quit)),
+            ["break", address] | ["b", address] => {
+                self.add_breakpoint(
+                    emulator,
+                    Breakpoint::Address(parse_number(address)?),
+                    output,
+                );
+            }
+            ["break-if", register, comparison, value] => {
+                let register = parse_register(register)?;
+                let comparison = parse_comparison(comparison)?;
+                let value = parse_number(value)? as u16;
+                let is_true = compare(get_register(emulator, register), comparison, value);
+                self.add_breakpoint(
+                    emulator,
+                    Breakpoint::Condition(register, comparison, value, is_true),
+                    output,
+                );
+            }
+            ["watch", address] | ["w", address] => {
+                self.add_breakpoint(
+                    emulator,
+                    Breakpoint::Watch(parse_number(address)?, None),
+                    output,
+                );
+            }
+            ["watch", address, access] | ["w", address, access] => {
+                let access = match *access {
+                    "read" => MemoryAccess::Read,
+                    "write" => MemoryAccess::Write,
+                    _ => return Err(format!("Unknown access \"{}\"", access)),
+                };
+                self.add_breakpoint(
+                    emulator,
+                    Breakpoint::Watch(parse_number(address)?, Some(access)),
+                    output,
+                );
+            }
+            ["list"] | ["l"] => {
+                for (index, breakpoint) in self.breakpoints.iter().enumerate() {
+                    writeln!(output, "{}: {}", index, describe_breakpoint(breakpoint)).unwrap();
+                }
+            }
+            ["delete", index] | ["d", index] => {
+                let index = parse_number(index)?;
+                if index >= self.breakpoints.len() {
+                    return Err(format!("No breakpoint {}", index));
+                }
+                self.breakpoints.remove(index);
+                self.update_tracking(emulator);
+            }
+            ["registers"] | ["r"] => {
+                for x in 0..16 {
+                    write!(output, "V{:X}=0x{:02X} ", x, emulator.cpu.get_register(x)).unwrap();
+                }
+                writeln!(
+                    output,
+                    "\nI=0x{:03X} PC=0x{:03X} DT={} ST={}",
+                    emulator.cpu.get_i(),
+                    emulator.cpu.get_pc(),
+                    emulator.cpu.get_delay_timer(),
+                    emulator.cpu.get_sound_timer()
+                )
+                .unwrap();
+            }
+            ["set", register, value] => {
+                let register = parse_register(register)?;
+                let value = parse_number(value)?;
+                if register == 17 && value + 1 >= emulator.memory.get_size() {
+                    return Err("Address out of range".to_string());
+                }
+                set_register(emulator, register, value);
+            }
+            ["stack"] => {
+                for (depth, address) in emulator.cpu.get_stack().iter().enumerate().rev() {
+                    writeln!(output, "#{} 0x{:03X}", depth, address).unwrap();
+                }
+            }
+            ["memory", address] | ["m", address] => {
+                print_memory(emulator, parse_number(address)?, 16, output)?
+            }
+            ["memory", address, length] | ["m", address, length] => print_memory(
+                emulator,
+                parse_number(address)?,
+                parse_number(length)?,
+                output,
+            )?,
+            ["poke", address, values @ ..] if !values.is_empty() => {
+                let address = parse_number(address)?;
+                if address + values.len() > emulator.memory.get_size() {
+                    return Err("Address out of range".to_string());
+                }
+                for (offset, value) in values.iter().enumerate() {
+                    emulator
+                        .memory
+                        .write(address + offset, parse_number(value)? as u8);
+                }
+            }
+            ["help"] | ["h"] => writeln!(output, "{}", HELP).unwrap(),
+            _ => return Err("Unknown command, enter \"help\" for a list of commands".to_string()),
+        }
+
+        Ok(None)
+    }
+
+    fn add_breakpoint(
+        &mut self,
+        emulator: &mut Emulator,
+        breakpoint: Breakpoint,
+        output: &mut dyn Write,
+    ) {
+        writeln!(
+            output,
+            "Breakpoint {}: {}",
+            self.breakpoints.len(),
+            describe_breakpoint(&breakpoint)
+        )
+        .unwrap();
+        self.breakpoints.push(breakpoint);
+        self.update_tracking(emulator);
+    }
+
+    // Memory accesses are only tracked while there are watchpoints
+    fn update_tracking(&self, emulator: &mut Emulator) {
+        let watching = self.breakpoints.iter().any(|breakpoint| match breakpoint {
+            Breakpoint::Watch(..) => true,
+            _ => false,
+        });
+        emulator.memory.set_tracking(watching);
+    }
+}
+
+fn print_instruction(emulator: &mut Emulator, output: &mut dyn Write) {
+    let pc = emulator.cpu.get_pc();
+    if pc + 1 >= emulator.memory.get_size() {
+        writeln!(output, "0x{:03X}: outside of memory", pc).unwrap();
+        return;
+    }
+
+    let opcode = (emulator.memory.read(pc) as u16) << 8 | emulator.memory.read(pc + 1) as u16;
+    let instruction = disassemble_at(&emulator.memory, pc, emulator.cpu.get_mode());
+    emulator.memory.take_accesses();
+    writeln!(output, "0x{:03X}: {:04X} {}", pc, opcode, instruction).unwrap();
+}
+
+fn print_memory(
+    emulator: &mut Emulator,
+    address: usize,
+    length: usize,
+    output: &mut dyn Write,
+) -> Result<(), String> {
+    if address + length > emulator.memory.get_size() {
+        return Err("Address out of range".to_string());
+    }
+
+    for row in (address..(address + length)).step_by(16) {
+        write!(output, "0x{:03X} ", row).unwrap();
+        for index in row..(row + 16).min(address + length) {
+            write!(output, "{:02X} ", emulator.memory.read(index)).unwrap();
+        }
+        writeln!(output).unwrap();
+    }
+    emulator.memory.take_accesses();
+
+    Ok(())
+}
+
+fn describe_breakpoint(breakpoint: &Breakpoint) -> String {
+    match breakpoint {
+        Breakpoint::Address(address) => format!("PC == 0x{:03X}", address),
+        Breakpoint::Watch(address, None) => format!("access of 0x{:03X}", address),
+        Breakpoint::Watch(address, Some(access)) => format!("{:?} of 0x{:03X}", access, address),
+        Breakpoint::Condition(register, comparison, value, _) => format!(
+            "{} {} 0x{:X}",
+            REGISTER_NAMES[*register].to_uppercase(),
+            match comparison {
+                Comparison::Equal => "==",
+                Comparison::NotEqual => "!=",
+                Comparison::Less => "<",
+                Comparison::LessOrEqual => "<=",
+                Comparison::Greater => ">",
+                Comparison::GreaterOrEqual => ">=",
+            },
+            value
+        ),
+    }
+}
+
+// Registers are indexed as in REGISTER_NAMES
+fn get_register(emulator: &Emulator, register: usize) -> u16 {
+    match register {
+        0..=15 => emulator.cpu.get_register(register) as u16,
+        16 => emulator.cpu.get_i(),
+        17 => emulator.cpu.get_pc() as u16,
+        18 => emulator.cpu.get_delay_timer() as u16,
+        _ => emulator.cpu.get_sound_timer() as u16,
+    }
+}
+
+fn set_register(emulator: &mut Emulator, register: usize, value: usize) {
+    match register {
+        0..=15 => emulator.cpu.set_register(register, value as u8),
+        16 => emulator.cpu.set_i(value as u16),
+        17 => emulator.cpu.set_pc(value),
+        18 => emulator.cpu.set_delay_timer(value as u8),
+        _ => {
+            emulator.cpu.set_sound_timer(value as u8);
+            emulator.speaker.set_tone(value as u8 > 0);
+        }
+    }
+}
+
+fn compare(left: u16, comparison: Comparison, right: u16) -> bool {
+    match comparison {
+        Comparison::Equal => left == right,
+        Comparison::NotEqual => left != right,
+        Comparison::Less => left < right,
+        Comparison::LessOrEqual => left <= right,
+        Comparison::Greater => left > right,
+        Comparison::GreaterOrEqual => left >= right,
+    }
+}
+
+fn parse_number(value: &str) -> Result<usize, String> {
+    let result = if value.starts_with("0x") || value.starts_with("0X") {
+        usize::from_str_radix(&value[2..], 16)
+    } else {
+        value.parse()
+    };
+    result.map_err(|_| format!("Invalid number \"{}\"", value))
+}
+
+fn parse_register(name: &str) -> Result<usize, String> {
+    REGISTER_NAMES
+        .iter()
+        .position(|register| *register == name.to_lowercase())
+        .ok_or_else(|| format!("Unknown register \"{}\"", name))
+}
+
+fn parse_comparison(comparison: &str) -> Result<Comparison, String> {
+    match comparison {
+        "==" => Ok(Comparison::Equal),
+        "!=" => Ok(Comparison::NotEqual),
+        "<" => Ok(Comparison::Less),
+        "<=" => Ok(Comparison::LessOrEqual),
+        ">" => Ok(Comparison::Greater),
+        ">=" => Ok(Comparison::GreaterOrEqual),
+        _ => Err(format!("Unknown comparison \"{}\"", comparison)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Quirks;
+    use std::io::Cursor;
+
+    fn instantiate_emulator(program: Vec<u8>) -> Emulator {
+        let mut emulator = Emulator::new(600.0, false, 0x200, Mode::Chip8, Quirks::default());
+        emulator.load_rom(&mut Cursor::new(program)).unwrap();
+        emulator
+    }
+
+    // Sends commands to the prompt and runs until the debugger pauses again, returns the output
+    fn debug(debugger: &mut Debugger, emulator: &mut Emulator, commands: &str) -> String {
+        let mut output = Vec::new();
+        let action = debugger.prompt(
+            emulator,
+            None,
+            &mut Cursor::new(commands.as_bytes()),
+            &mut output,
+        );
+        assert_eq!(action, DebuggerAction::Resume);
+
+        for _ in 0..1000 {
+            if debugger.should_break(emulator, &mut output) {
+                break;
+            }
+            emulator.step(false, false).unwrap();
+            debugger.check_watchpoints(emulator, &mut output);
+        }
+
+        String::from_utf8(output).unwrap()
+    }
+
+    // 0x200: CALL 0x208, 0x202: LD V1, 0x01, 0x204: JP 0x204, 0x206: filler
+    // 0x208: LD V0, 0x05, 0x20A: LD I, 0x300, 0x20C: LD [I], V0, 0x20E: RET
+    fn instantiate_program() -> Emulator {
+        instantiate_emulator(vec![
+            0x22, 0x08, 0x61, 0x01, 0x12, 0x04, 0x00, 0x00, //
+            0x60, 0x05, 0xA3, 0x00, 0xF0, 0x55, 0x00, 0xEE,
+        ])
+    }
+
+    #[test]
+    fn test_step() {
+        let mut emulator = instantiate_program();
+        let mut debugger = Debugger::new(true);
+
+        debug(&mut debugger, &mut emulator, "step 2\n");
+
+        assert_eq!(emulator.cpu.get_pc(), 0x20A);
+        assert_eq!(emulator.cpu.get_register(0x0), 0x05);
+    }
+
+    #[test]
+    fn test_step_over_call() {
+        let mut emulator = instantiate_program();
+        let mut debugger = Debugger::new(true);
+
+        debug(&mut debugger, &mut emulator, "next\n");
+
+        assert_eq!(emulator.cpu.get_pc(), 0x202);
+        assert_eq!(emulator.cpu.get_stack().len(), 0);
+    }
+
+    #[test]
+    fn test_finish_subroutine() {
+        let mut emulator = instantiate_program();
+        let mut debugger = Debugger::new(true);
+
+        debug(&mut debugger, &mut emulator, "step\n");
+        debug(&mut debugger, &mut emulator, "finish\n");
+
+        assert_eq!(emulator.cpu.get_pc(), 0x202);
+    }
+
+    #[test]
+    fn test_address_breakpoint() {
+        let mut emulator = instantiate_program();
+        let mut debugger = Debugger::new(true);
+
+        let output = debug(&mut debugger, &mut emulator, "break 0x20C\ncontinue\n");
+
+        assert_eq!(emulator.cpu.get_pc(), 0x20C);
+        assert!(output.contains("Breakpoint 0 reached"));
+    }
+
+    #[test]
+    fn test_write_watchpoint() {
+        let mut emulator = instantiate_program();
+        let mut debugger = Debugger::new(true);
+
+        let output = debug(&mut debugger, &mut emulator, "watch 0x300 write\nc\n");
+
+        assert_eq!(emulator.cpu.get_pc(), 0x20E);
+        assert!(output.contains("Watchpoint 0 hit: Write of 0x300"));
+    }
+
+    #[test]
+    fn test_sprite_read_watchpoint() {
+        // 0x200: LD I, 0x208, 0x202: DRW V0, V0, 1, 0x204: JP 0x204, 0x206: filler, 0x208: sprite
+        let mut emulator = instantiate_emulator(vec![
+            0xA2, 0x08, 0xD0, 0x01, 0x12, 0x04, 0x00, 0x00, //
+            0xFF,
+        ]);
+        let mut debugger = Debugger::new(true);
+
+        let output = debug(
+            &mut debugger,
+            &mut emulator,
+            "watch 0x208 read
+c
+",
+        );
+
+        assert_eq!(emulator.cpu.get_pc(), 0x204);
+        assert!(output.contains("Watchpoint 0 hit: Read of 0x208"));
+    }
+
+    #[test]
+    fn test_condition_breakpoint() {
+        let mut emulator = instantiate_program();
+        let mut debugger = Debugger::new(true);
+
+        let output = debug(&mut debugger, &mut emulator, "break-if v1 == 1\nc\n");
+
+        assert_eq!(emulator.cpu.get_register(0x1), 0x01);
+        assert_eq!(emulator.cpu.get_pc(), 0x204);
+        assert!(output.contains("Breakpoint 0 condition is true"));
+    }
+
+    #[test]
+    fn test_modify_registers_and_memory() {
+        let mut emulator = instantiate_program();
+        let mut debugger = Debugger::new(true);
+
+        debug(
+            &mut debugger,
+            &mut emulator,
+            "set va 0x2A\nset i 0x300\nset dt 30\npoke 0x300 1 2 3\nset pc 0x204\nstep\n",
+        );
+
+        assert_eq!(emulator.cpu.get_register(0xA), 0x2A);
+        assert_eq!(emulator.cpu.get_i(), 0x300);
+        assert_eq!(emulator.memory.read(0x302), 3);
+        assert_eq!(emulator.cpu.get_pc(), 0x204);
+    }
+
+    #[test]
+    fn test_step_back() {
+        let mut emulator = instantiate_program();
+        let mut rewind = RewindBuffer::new(1 << 20, 1);
+        rewind.record(&mut emulator);
+        let mut debugger = Debugger::new(true);
+        debug(&mut debugger, &mut emulator, "step 3\n");
+        assert_eq!(emulator.cpu.get_pc(), 0x20C);
+
+        let mut output = Vec::new();
+        debugger.prompt(
+            &mut emulator,
+            Some(&mut rewind),
+            &mut Cursor::new("back 2\nc\n".as_bytes()),
+            &mut output,
+        );
+
+        assert_eq!(emulator.cpu.get_pc(), 0x208);
+        assert_eq!(emulator.cpu.get_register(0x0), 0x00);
+        assert!(String::from_utf8(output).unwrap().contains("0x208: 6005"));
+    }
+
+    #[test]
+    fn test_unknown_command() {
+        let mut emulator = instantiate_program();
+        let mut debugger = Debugger::new(true);
+
+        let output = debug(&mut debugger, &mut emulator, "frobnicate\nset vz 1\nc\n");
+
+        assert!(output.contains("Unknown command"));
+        assert!(output.contains("Unknown register \"vz\""));
+    }
+
+    #[test]
+    fn test_set_pc_out_of_range() {
+        let mut emulator = instantiate_program();
+        let mut debugger = Debugger::new(true);
+
+        let output = debug(
+            &mut debugger,
+            &mut emulator,
+            "set pc 0xFFF
+next
+",
+        );
+
+        assert!(output.contains("Address out of range"));
+        assert_eq!(emulator.cpu.get_pc(), 0x202);
+    }
+}
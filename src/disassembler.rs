@@ -0,0 +1,418 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Error, Write};
+
+use crate::cpu::Mode;
+use crate::memory::MemoryTrait;
+
+// Effect of an instruction on the program counter, used to find the reachable code
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Flow {
+    Next,               // continues with the next instruction
+    Skip,               // continues with the next or the one after it
+    Jump(usize),        // continues at the address
+    Call(usize),        // continues at the address and after returning with the next instruction
+    JumpIndexed(usize), // continues at the address plus a register (jump table)
+    Stop,               // returns, exits or cannot be executed
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operand {
+    Register(usize),
+    Byte(u8),
+    Nibble(u8),
+    Address(usize), // replaced by its label in listings
+    LongAddress(usize),
+    Text(&'static str),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Instruction {
+    pub mnemonic: &'static str,
+    pub operands: Vec<Operand>,
+    pub length: usize, // in bytes
+    pub flow: Flow,
+}
+
+impl Instruction {
+    fn new(mnemonic: &'static str, operands: Vec<Operand>, flow: Flow) -> Instruction {
+        Instruction {
+            mnemonic: mnemonic,
+            operands: operands,
+            length: 2,
+            flow: flow,
+        }
+    }
+
+    // Formats the instruction in the syntax of Cowgod's Chip-8 Technical Reference, e.g. "LD V3, 0x1F"
+    pub fn format(&self, labels: &HashMap<usize, String>) -> String {
+        let operands: Vec<String> = self
+            .operands
+            .iter()
+            .map(|operand| match operand {
+                Operand::Register(x) => format!("V{:X}", x),
+                Operand::Byte(byte) => format!("0x{:02X}", byte),
+                Operand::Nibble(nibble) => format!("{}", nibble),
+                Operand::Address(address) => format_address(*address, labels),
+                Operand::LongAddress(address) => {
+                    format!("long {}", format_address(*address, labels))
+                }
+                Operand::Text(text) => text.to_string(),
+            })
+            .collect();
+
+        if operands.is_empty() {
+            self.mnemonic.to_string()
+        } else {
+            format!("{} {}", self.mnemonic, operands.join(", "))
+        }
+    }
+}
+
+// Decodes the instruction at the start of the bytes, returns None for unknown instructions
+pub fn decode(bytes: &[u8], mode: Mode) -> Option<Instruction> {
+    use self::Flow::*;
+    use self::Operand::*;
+
+    if bytes.len() < 2 {
+        return None;
+    }
+    let opcode = (bytes[0] as u16) << 8 | bytes[1] as u16;
+    let x = ((opcode & 0x0F00) >> 8) as usize;
+    let y = ((opcode & 0x00F0) >> 4) as usize;
+    let n = (opcode & 0x000F) as u8;
+    let kk = (opcode & 0x00FF) as u8;
+    let nnn = (opcode & 0x0FFF) as usize;
+
+    let super_chip = mode == Mode::SuperChip || mode == Mode::XoChip;
+    let xo_chip = mode == Mode::XoChip;
+    let instruction = Instruction::new;
+
+    let decoded = match ((opcode & 0xF000) >> 12, x, y, n) {
+        (0x0, 0x0, 0xC, _) if super_chip => instruction("SCD", vec![Nibble(n)], Next),
+        (0x0, 0x0, 0xD, _) if xo_chip => instruction("SCU", vec![Nibble(n)], Next),
+        (0x0, 0x0, 0xE, 0x0) => instruction("CLS", vec![], Next),
+        (0x0, 0x0, 0xE, 0xE) => instruction("RET", vec![], Stop),
+        (0x0, 0x0, 0xF, 0xB) if super_chip => instruction("SCR", vec![], Next),
+        (0x0, 0x0, 0xF, 0xC) if super_chip => instruction("SCL", vec![], Next),
+        (0x0, 0x0, 0xF, 0xD) if super_chip => instruction("EXIT", vec![], Stop),
+        (0x0, 0x0, 0xF, 0xE) if super_chip => instruction("LOW", vec![], Next),
+        (0x0, 0x0, 0xF, 0xF) if super_chip => instruction("HIGH", vec![], Next),
+        (0x0, _, _, _) => instruction("SYS", vec![Address(nnn)], Stop), // Not supported by the emulator
+        (0x1, _, _, _) => instruction("JP", vec![Address(nnn)], Jump(nnn)),
+        (0x2, _, _, _) => instruction("CALL", vec![Address(nnn)], Call(nnn)),
+        (0x3, _, _, _) => instruction("SE", vec![Register(x), Byte(kk)], Skip),
+        (0x4, _, _, _) => instruction("SNE", vec![Register(x), Byte(kk)], Skip),
+        (0x5, _, _, 0x0) => instruction("SE", vec![Register(x), Register(y)], Skip),
+        (0x5, _, _, 0x2) if xo_chip => instruction("SAVE", vec![Register(x), Register(y)], Next),
+        (0x5, _, _, 0x3) if xo_chip => instruction("LOAD", vec![Register(x), Register(y)], Next),
+        (0x6, _, _, _) => instruction("LD", vec![Register(x), Byte(kk)], Next),
+        (0x7, _, _, _) => instruction("ADD", vec![Register(x), Byte(kk)], Next),
+        (0x8, _, _, 0x0) => instruction("LD", vec![Register(x), Register(y)], Next),
+        (0x8, _, _, 0x1) => instruction("OR", vec![Register(x), Register(y)], Next),
+        (0x8, _, _, 0x2) => instruction("AND", vec![Register(x), Register(y)], Next),
+        (0x8, _, _, 0x3) => instruction("XOR", vec![Register(x), Register(y)], Next),
+        (0x8, _, _, 0x4) => instruction("ADD", vec![Register(x), Register(y)], Next),
+        (0x8, _, _, 0x5) => instruction("SUB", vec![Register(x), Register(y)], Next),
+        (0x8, _, _, 0x6) => instruction("SHR", vec![Register(x), Register(y)], Next),
+        (0x8, _, _, 0x7) => instruction("SUBN", vec![Register(x), Register(y)], Next),
+        (0x8, _, _, 0xE) => instruction("SHL", vec![Register(x), Register(y)], Next),
+        (0x9, _, _, 0x0) => instruction("SNE", vec![Register(x), Register(y)], Skip),
+        (0xA, _, _, _) => instruction("LD", vec![Text("I"), Address(nnn)], Next),
+        (0xB, _, _, _) => instruction("JP", vec![Register(0), Address(nnn)], JumpIndexed(nnn)),
+        (0xC, _, _, _) => instruction("RND", vec![Register(x), Byte(kk)], Next),
+        (0xD, _, _, _) => instruction("DRW", vec![Register(x), Register(y), Nibble(n)], Next),
+        (0xE, _, 0x9, 0xE) => instruction("SKP", vec![Register(x)], Skip),
+        (0xE, _, 0xA, 0x1) => instruction("SKNP", vec![Register(x)], Skip),
+        (0xF, 0x0, 0x0, 0x0) if xo_chip && bytes.len() >= 4 => {
+            let address = (bytes[2] as usize) << 8 | bytes[3] as usize;
+            Instruction {
+                length: 4,
+                ..instruction("LD", vec![Text("I"), LongAddress(address)], Next)
+            }
+        }
+        (0xF, _, 0x0, 0x1) if xo_chip => instruction("PLANE", vec![Nibble(x as u8)], Next),
+        (0xF, 0x0, 0x0, 0x2) if xo_chip => instruction("AUDIO", vec![], Next),
+        (0xF, _, 0x0, 0x7) => instruction("LD", vec![Register(x), Text("DT")], Next),
+        (0xF, _, 0x0, 0xA) => instruction("LD", vec![Register(x), Text("K")], Next),
+        (0xF, _, 0x1, 0x5) => instruction("LD", vec![Text("DT"), Register(x)], Next),
+        (0xF, _, 0x1, 0x8) => instruction("LD", vec![Text("ST"), Register(x)], Next),
+        (0xF, _, 0x1, 0xE) => instruction("ADD", vec![Text("I"), Register(x)], Next),
+        (0xF, _, 0x2, 0x9) => instruction("LD", vec![Text("F"), Register(x)], Next),
+        (0xF, _, 0x3, 0x0) if super_chip => instruction("LD", vec![Text("HF"), Register(x)], Next),
+        (0xF, _, 0x3, 0x3) => instruction("LD", vec![Text("B"), Register(x)], Next),
+        (0xF, _, 0x3, 0xA) if xo_chip => instruction("PITCH", vec![Register(x)], Next),
+        (0xF, _, 0x5, 0x5) => instruction("LD", vec![Text("[I]"), Register(x)], Next),
+        (0xF, _, 0x6, 0x5) => instruction("LD", vec![Register(x), Text("[I]")], Next),
+        (0xF, _, 0x7, 0x5) if super_chip => instruction("LD", vec![Text("R"), Register(x)], Next),
+        (0xF, _, 0x8, 0x5) if super_chip => instruction("LD", vec![Register(x), Text("R")], Next),
+        _ => return None,
+    };
+
+    Some(decoded)
+}
+
+// Formats the instruction at an address of the memory, e.g. for debug output
+pub fn disassemble_at(memory: &dyn MemoryTrait, address: usize, mode: Mode) -> String {
+    let end = (address + 4).min(memory.get_size());
+    let bytes: Vec<u8> = (address..end).map(|index| memory.read(index)).collect();
+
+    match decode(&bytes, mode) {
+        Some(instruction) => instruction.format(&HashMap::new()),
+        None => "?".to_string(),
+    }
+}
+
+// Writes a labelled listing of a ROM. Only instructions that are reachable from the entry point are
+// treated as code, everything else is listed as data.
+pub fn disassemble(
+    writer: &mut dyn Write,
+    rom: &[u8],
+    program_address: usize,
+    mode: Mode,
+) -> Result<(), Error> {
+    let code = find_code(rom, program_address, mode);
+    let labels = find_labels(&code, rom, program_address);
+
+    let mut address = program_address;
+    let end = program_address + rom.len();
+    while address < end {
+        if let Some(label) = labels.get(&address) {
+            writeln!(writer, "{}:", label)?;
+        }
+
+        let offset = address - program_address;
+        let length = match code.get(&address) {
+            Some(instruction) => {
+                let bytes = &rom[offset..(offset + instruction.length)];
+                writeln!(
+                    writer,
+                    "    0x{:03X}  {:<12} {}",
+                    address,
+                    format_bytes(bytes),
+                    instruction.format(&labels)
+                )?;
+                instruction.length
+            }
+            None => {
+                // Up to four bytes per line, a line ends before code and labels
+                let mut length = 1;
+                while length < 4
+                    && address + length < end
+                    && !code.contains_key(&(address + length))
+                    && !labels.contains_key(&(address + length))
+                {
+                    length += 1;
+                }
+
+                let bytes = &rom[offset..(offset + length)];
+                let values: Vec<String> =
+                    bytes.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+                writeln!(
+                    writer,
+                    "    0x{:03X}  {:<12} db {}",
+                    address,
+                    format_bytes(bytes),
+                    values.join(", ")
+                )?;
+                length
+            }
+        };
+
+        address += length;
+    }
+
+    Ok(())
+}
+
+// Follows the control flow from the entry point, instructions overlapping earlier ones are ignored
+fn find_code(rom: &[u8], program_address: usize, mode: Mode) -> BTreeMap<usize, Instruction> {
+    let mut reachable: BTreeMap<usize, Instruction> = BTreeMap::new();
+    let mut pending = vec![program_address];
+    let end = program_address + rom.len();
+
+    while let Some(address) = pending.pop() {
+        if address < program_address || address >= end || reachable.contains_key(&address) {
+            continue;
+        }
+        let instruction = match decode(&rom[(address - program_address)..], mode) {
+            Some(instruction) => instruction,
+            None => continue,
+        };
+
+        let next = address + instruction.length;
+        match instruction.flow {
+            Flow::Next => pending.push(next),
+            Flow::Skip => {
+                // XO-CHIP skips the four byte long F000 nnnn as a whole
+                let skipped = match decode(&rom[(next - program_address).min(rom.len())..], mode) {
+                    Some(skipped) => skipped.length,
+                    None => 2,
+                };
+                pending.push(next);
+                pending.push(next + skipped);
+            }
+            Flow::Jump(target) | Flow::JumpIndexed(target) => pending.push(target),
+            Flow::Call(target) => {
+                pending.push(target);
+                pending.push(next);
+            }
+            Flow::Stop => (),
+        }
+        reachable.insert(address, instruction);
+    }
+
+    let mut code = BTreeMap::new();
+    let mut covered = program_address;
+    for (address, instruction) in reachable {
+        if address >= covered {
+            covered = address + instruction.length;
+            code.insert(address, instruction);
+        }
+    }
+    code
+}
+
+// Names the entry point, subroutines, jump targets and addresses loaded into I
+fn find_labels(
+    code: &BTreeMap<usize, Instruction>,
+    rom: &[u8],
+    program_address: usize,
+) -> HashMap<usize, String> {
+    let in_rom =
+        |address: usize| address >= program_address && address < program_address + rom.len();
+    let mut labels = HashMap::new();
+
+    for instruction in code.values() {
+        if let Flow::Call(target) = instruction.flow {
+            labels.insert(target, format!("sub_{:03X}", target));
+        }
+    }
+    for instruction in code.values() {
+        match instruction.flow {
+            Flow::Jump(target) | Flow::JumpIndexed(target) => {
+                labels
+                    .entry(target)
+                    .or_insert_with(|| format!("loc_{:03X}", target));
+            }
+            _ => (),
+        }
+    }
+    for instruction in code.values() {
+        if instruction.mnemonic == "LD" && instruction.operands[0] == Operand::Text("I") {
+            if let Operand::Address(target) | Operand::LongAddress(target) = instruction.operands[1]
+            {
+                labels
+                    .entry(target)
+                    .or_insert_with(|| format!("data_{:03X}", target));
+            }
+        }
+    }
+    labels.insert(program_address, "start".to_string());
+
+    // Targets inside an instruction have no line of their own, their operands stay numeric
+    let inside_code = |address: usize| {
+        code.range(address.saturating_sub(3)..address)
+            .any(|(start, instruction)| start + instruction.length > address)
+    };
+    labels.retain(|address, _| in_rom(*address) && !inside_code(*address));
+    labels
+}
+
+fn format_address(address: usize, labels: &HashMap<usize, String>) -> String {
+    match labels.get(&address) {
+        Some(label) => label.clone(),
+        None => format!("0x{:03X}", address),
+    }
+}
+
+fn format_bytes(bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+    bytes.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(bytes: &[u8], mode: Mode) -> String {
+        decode(bytes, mode).unwrap().format(&HashMap::new())
+    }
+
+    fn disassemble_to_string(rom: &[u8], mode: Mode) -> String {
+        let mut listing = Vec::new();
+        disassemble(&mut listing, rom, 0x200, mode).unwrap();
+        String::from_utf8(listing).unwrap()
+    }
+
+    #[test]
+    fn test_decode_operands() {
+        assert_eq!(format(&[0x63, 0x1F], Mode::Chip8), "LD V3, 0x1F");
+        assert_eq!(format(&[0xD0, 0x15], Mode::Chip8), "DRW V0, V1, 5");
+        assert_eq!(format(&[0x8A, 0xB4], Mode::Chip8), "ADD VA, VB");
+        assert_eq!(format(&[0xA2, 0x3C], Mode::Chip8), "LD I, 0x23C");
+        assert_eq!(format(&[0xB3, 0x00], Mode::Chip8), "JP V0, 0x300");
+        assert_eq!(format(&[0xF4, 0x65], Mode::Chip8), "LD V4, [I]");
+        assert_eq!(format(&[0xE1, 0x9E], Mode::Chip8), "SKP V1");
+        assert_eq!(format(&[0x00, 0xE0], Mode::Chip8), "CLS");
+    }
+
+    #[test]
+    fn test_decode_depends_on_mode() {
+        assert_eq!(format(&[0x00, 0xFF], Mode::Chip8), "SYS 0x0FF");
+        assert_eq!(format(&[0x00, 0xFF], Mode::SuperChip), "HIGH");
+        assert_eq!(decode(&[0xF2, 0x01], Mode::SuperChip), None);
+        assert_eq!(format(&[0xF2, 0x01], Mode::XoChip), "PLANE 2");
+
+        let long = decode(&[0xF0, 0x00, 0x12, 0x34], Mode::XoChip).unwrap();
+        assert_eq!(long.length, 4);
+        assert_eq!(long.format(&HashMap::new()), "LD I, long 0x1234");
+    }
+
+    #[test]
+    fn test_decode_unknown() {
+        assert_eq!(decode(&[0x80, 0x08], Mode::Chip8), None);
+        assert_eq!(decode(&[0xF0], Mode::Chip8), None);
+    }
+
+    #[test]
+    fn test_disassemble_listing() {
+        let rom = [
+            0x22, 0x06, // CALL 0x206
+            0xA2, 0x0A, // LD I, 0x20A
+            0x12, 0x04, // JP 0x204
+            0x60, 0x01, // LD V0, 0x01
+            0x00, 0xEE, // RET
+            0xF0, 0x90, 0xFF, // Sprite data
+        ];
+
+        assert_eq!(
+            disassemble_to_string(&rom, Mode::Chip8),
+            "start:
+    0x200  22 06        CALL sub_206
+    0x202  A2 0A        LD I, data_20A
+loc_204:
+    0x204  12 04        JP loc_204
+sub_206:
+    0x206  60 01        LD V0, 0x01
+    0x208  00 EE        RET
+data_20A:
+    0x20A  F0 90 FF     db 0xF0, 0x90, 0xFF
+"
+        );
+    }
+
+    #[test]
+    fn test_disassemble_skips() {
+        let rom = [
+            0x30, 0x00, // SE V0, 0x00
+            0x12, 0x00, // JP 0x200
+            0x00, 0xEE, // RET
+            0x12, 0x34, // Unreachable
+        ];
+
+        let listing = disassemble_to_string(&rom, Mode::Chip8);
+
+        assert!(listing.contains("0x204  00 EE        RET"));
+        assert!(listing.contains("0x206  12 34        db 0x12, 0x34"));
+    }
+}
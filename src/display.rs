@@ -1,138 +1,300 @@
-extern crate sdl2;
-
-use sdl2::pixels::Color;
-use sdl2::rect::Rect;
-use sdl2::video::Window;
+use crate::image::*;
+use crate::quirks::Quirks;
+use crate::savestate::{StateReader, StateWriter};
+use std::io::{Error, Write};
 
 #[cfg(test)]
 use mockall::{automock, predicate::*};
 
 pub const DISPLAY_WIDTH: usize = 64;
 pub const DISPLAY_HEIGHT: usize = 32;
-const COLOR_ON: sdl2::pixels::Color = Color::RGB(109, 170, 44);
-const COLOR_OFF: sdl2::pixels::Color = Color::RGB(2, 95, 95);
+pub const HIRES_DISPLAY_WIDTH: usize = 128; // SUPER-CHIP high resolution mode
+pub const HIRES_DISPLAY_HEIGHT: usize = 64; // SUPER-CHIP high resolution mode
+pub const DISPLAY_PLANES: usize = 2; // XO-CHIP bit-planes
+
+/// RGB colors, indexed by the bit-planes that are set for a pixel
+pub const PALETTE: [(u8, u8, u8); 1 << DISPLAY_PLANES] = [
+    (2, 95, 95),     // Off
+    (109, 170, 44),  // Plane 1
+    (228, 120, 32),  // Plane 2
+    (240, 240, 200), // Both planes
+];
 
+/// Monochrome display with up to two bit-planes (XO-CHIP) in low or high resolution
 #[cfg_attr(test, automock)]
 pub trait DisplayTrait {
-    fn create_window(
-        &self,
-        sdl_video: &sdl2::VideoSubsystem,
-        title_addition: &str,
-    ) -> sdl2::video::Window;
+    /// XORs an 8 pixel wide sprite onto the selected planes, returns 1 if a pixel was erased
     fn draw_sprite(&mut self, x: usize, y: usize, sprite: &[u8]) -> u8;
+    /// XORs a 16x16 sprite (SUPER-CHIP), returns 1 if a pixel was erased
+    fn draw_large_sprite(&mut self, x: usize, y: usize, sprite: &[u8]) -> u8;
+    fn scroll_down(&mut self, rows: usize);
+    fn scroll_up(&mut self, rows: usize);
+    fn scroll_right(&mut self, columns: usize);
+    fn scroll_left(&mut self, columns: usize);
+    fn set_high_resolution(&mut self, high_resolution: bool);
+    /// Selects the bit-planes affected by drawing, scrolling and clearing
+    fn select_planes(&mut self, planes: u8);
+    fn get_selected_planes(&self) -> u8;
+    /// Whether the display changed since it was last rendered
     fn needs_redraw(&self) -> bool;
-    fn draw(&mut self, renderer: &mut sdl2::render::Canvas<Window>);
+    /// Clears the selected planes
     fn clear(&mut self);
 }
 
+/// Direct pixel access for the debugger and tests
 pub trait DisplayDebugTrait {
     fn read_pixel(&self, x: usize, y: usize) -> bool;
+    fn read_pixel_planes(&self, x: usize, y: usize) -> u8;
     fn write_pixel(&mut self, x: usize, y: usize, value: bool);
 }
 
+/// Display of the emulator, large enough for the SUPER-CHIP high resolution
 pub struct Display {
     // General
-    pixels: [[bool; DISPLAY_HEIGHT]; DISPLAY_WIDTH],
+    pixels: [[u8; HIRES_DISPLAY_HEIGHT]; HIRES_DISPLAY_WIDTH], // One bit per plane, large enough for both resolutions
+    high_resolution: bool,
+    selected_planes: u8, // Bit mask of the planes affected by drawing, scrolling and clearing
     needs_redraw: bool,
 
     // Configuration
-    display_scale: u8,
+    quirks: Quirks,
 }
 
 impl DisplayTrait for Display {
-    fn create_window(
-        &self,
-        sdl_video: &sdl2::VideoSubsystem,
-        title_addition: &str,
-    ) -> sdl2::video::Window {
-        let title = format!("Chip-8 Emulator ({})", title_addition);
-
-        sdl_video
-            .window(
-                &title,
-                DISPLAY_WIDTH as u32 * self.display_scale as u32,
-                DISPLAY_HEIGHT as u32 * self.display_scale as u32,
-            )
-            .position_centered()
-            .opengl()
-            .build()
-            .unwrap()
+    fn draw_sprite(&mut self, x: usize, y: usize, sprite: &[u8]) -> u8 {
+        self.xor_sprite(x, y, sprite, 1)
     }
 
-    fn draw_sprite(&mut self, x: usize, y: usize, sprite: &[u8]) -> u8 {
-        self.needs_redraw = true;
-        let mut collision = 0;
+    fn draw_large_sprite(&mut self, x: usize, y: usize, sprite: &[u8]) -> u8 {
+        self.xor_sprite(x, y, sprite, 2)
+    }
 
-        for row in 0..sprite.len() as usize {
-            for column in 0..8 as usize {
-                let xp = (x + column) % DISPLAY_WIDTH as usize;
-                let yp = (y + row) % DISPLAY_HEIGHT as usize;
+    fn scroll_down(&mut self, rows: usize) {
+        self.shift(0, rows as isize);
+    }
 
-                let previous_state = self.pixels[xp][yp];
-                self.pixels[xp][yp] ^= Display::get_bit(sprite[row], column as u8);
-                if previous_state && !self.pixels[xp][yp] {
-                    collision = 1;
-                }
-            }
-        }
+    fn scroll_up(&mut self, rows: usize) {
+        self.shift(0, -(rows as isize));
+    }
 
-        collision
+    fn scroll_right(&mut self, columns: usize) {
+        self.shift(columns as isize, 0);
     }
 
-    fn needs_redraw(&self) -> bool {
-        self.needs_redraw
+    fn scroll_left(&mut self, columns: usize) {
+        self.shift(-(columns as isize), 0);
     }
 
-    fn draw(&mut self, renderer: &mut sdl2::render::Canvas<Window>) {
-        for x in 0..DISPLAY_WIDTH {
-            for y in 0..DISPLAY_HEIGHT {
-                if self.pixels[x as usize][y as usize] {
-                    renderer.set_draw_color(COLOR_ON);
-                } else {
-                    renderer.set_draw_color(COLOR_OFF);
-                }
+    fn set_high_resolution(&mut self, high_resolution: bool) {
+        self.high_resolution = high_resolution;
+        self.pixels = [[0; HIRES_DISPLAY_HEIGHT]; HIRES_DISPLAY_WIDTH];
+        self.needs_redraw = true;
+    }
 
-                renderer
-                    .fill_rect(Rect::new(
-                        x as i32 * self.display_scale as i32,
-                        y as i32 * self.display_scale as i32,
-                        self.display_scale as u32,
-                        self.display_scale as u32,
-                    ))
-                    .unwrap();
-            }
-        }
+    fn select_planes(&mut self, planes: u8) {
+        self.selected_planes = planes & ((1 << DISPLAY_PLANES) - 1) as u8;
+    }
 
-        renderer.present();
-        self.needs_redraw = false;
+    fn get_selected_planes(&self) -> u8 {
+        self.selected_planes
+    }
+
+    fn needs_redraw(&self) -> bool {
+        self.needs_redraw
     }
 
     fn clear(&mut self) {
-        self.pixels = [[false; DISPLAY_HEIGHT]; DISPLAY_WIDTH];
+        for column in self.pixels.iter_mut() {
+            for pixel in column.iter_mut() {
+                *pixel &= !self.selected_planes;
+            }
+        }
+        self.needs_redraw = true;
     }
 }
 
 impl DisplayDebugTrait for Display {
     fn read_pixel(&self, x: usize, y: usize) -> bool {
+        self.pixels[x][y] != 0
+    }
+
+    fn read_pixel_planes(&self, x: usize, y: usize) -> u8 {
         self.pixels[x][y]
     }
 
     fn write_pixel(&mut self, x: usize, y: usize, value: bool) {
-        self.pixels[x][y] = value;
+        if value {
+            self.pixels[x][y] |= self.selected_planes;
+        } else {
+            self.pixels[x][y] &= !self.selected_planes;
+        }
     }
 }
 
 impl Display {
-    pub fn new(display_scale: u8) -> Display {
-        println!("Initializing display");
-
+    /// Creates a blank display in low resolution
+    pub fn new(quirks: Quirks) -> Display {
         Display {
             // General
-            pixels: [[false; DISPLAY_HEIGHT]; DISPLAY_WIDTH],
-            needs_redraw: false,
+            pixels: [[0; HIRES_DISPLAY_HEIGHT]; HIRES_DISPLAY_WIDTH],
+            high_resolution: false,
+            selected_planes: 0b01,
+            needs_redraw: true,
 
             // Configuration
-            display_scale: display_scale,
+            quirks: quirks,
+        }
+    }
+
+    /// Clears all planes and resets the display to its initial state
+    pub fn reset(&mut self) {
+        self.high_resolution = false;
+        self.selected_planes = 0b01;
+        self.pixels = [[0; HIRES_DISPLAY_HEIGHT]; HIRES_DISPLAY_WIDTH];
+        self.needs_redraw = true;
+    }
+
+    /// Writes the pixels and the resolution to a save state
+    pub fn save_state(&self, state: &mut StateWriter) {
+        for column in self.pixels.iter() {
+            state.write_bytes(column);
+        }
+        state.write_bool(self.high_resolution);
+        state.write_u8(self.selected_planes);
+    }
+
+    /// Reads the state written by save_state
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), Error> {
+        for column in self.pixels.iter_mut() {
+            column.copy_from_slice(state.read_bytes(HIRES_DISPLAY_HEIGHT)?);
+        }
+        self.high_resolution = state.read_bool()?;
+        self.selected_planes = state.read_u8()?;
+        self.needs_redraw = true;
+
+        Ok(())
+    }
+
+    /// Returns the bit-planes that are set for a pixel, used as index into the palette
+    pub fn get_pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[x][y]
+    }
+
+    /// Exports the current resolution, every pixel is scaled to a square of scale * scale pixels
+    pub fn save_screenshot(
+        &self,
+        writer: &mut dyn Write,
+        format: ImageFormat,
+        scale: usize,
+    ) -> Result<(), Error> {
+        let (width, height) = (self.get_width() * scale, self.get_height() * scale);
+        let pixels = self.get_scaled_pixels(width, height);
+
+        write_image(writer, format, width, height, &pixels, &PALETTE)
+    }
+
+    /// Returns the current resolution stretched to an image size, row by row
+    pub fn get_scaled_pixels(&self, width: usize, height: usize) -> Vec<u8> {
+        let (display_width, display_height) = (self.get_width(), self.get_height());
+
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                pixels.push(self.pixels[x * display_width / width][y * display_height / height]);
+            }
+        }
+        pixels
+    }
+
+    /// Marks the display as rendered
+    pub fn set_redrawn(&mut self) {
+        self.needs_redraw = false;
+    }
+
+    /// Width of the current resolution in pixels
+    pub fn get_width(&self) -> usize {
+        if self.high_resolution {
+            HIRES_DISPLAY_WIDTH
+        } else {
+            DISPLAY_WIDTH
+        }
+    }
+
+    /// Height of the current resolution in pixels
+    pub fn get_height(&self) -> usize {
+        if self.high_resolution {
+            HIRES_DISPLAY_HEIGHT
+        } else {
+            DISPLAY_HEIGHT
+        }
+    }
+
+    // Sprites are 8 pixels (one byte) or 16 pixels (two bytes) wide.
+    // If multiple planes are selected, the sprite data contains one sprite per plane.
+    // The sprite origin always wraps around, the sprite itself is clipped or wrapped depending on the quirks.
+    fn xor_sprite(&mut self, x: usize, y: usize, sprite: &[u8], bytes_per_row: usize) -> u8 {
+        self.needs_redraw = true;
+        let (width, height) = (self.get_width(), self.get_height());
+        let (x, y) = (x % width, y % height);
+        let plane_count = self.selected_planes.count_ones() as usize;
+        if plane_count == 0 {
+            return 0;
+        }
+        let bytes_per_plane = sprite.len() / plane_count;
+        let mut collision = 0;
+
+        let mut offset = 0;
+        for plane in 0..DISPLAY_PLANES {
+            let plane_mask = 1 << plane;
+            if self.selected_planes & plane_mask == 0 {
+                continue;
+            }
+
+            let plane_sprite = &sprite[offset..(offset + bytes_per_plane)];
+            for row in 0..bytes_per_plane / bytes_per_row {
+                for column in 0..8 * bytes_per_row {
+                    if self.quirks.clipping && (x + column >= width || y + row >= height) {
+                        continue;
+                    }
+                    let xp = (x + column) % width;
+                    let yp = (y + row) % height;
+
+                    let byte = plane_sprite[row * bytes_per_row + column / 8];
+                    if !Display::get_bit(byte, (column % 8) as u8) {
+                        continue;
+                    }
+
+                    if self.pixels[xp][yp] & plane_mask != 0 {
+                        collision = 1;
+                    }
+                    self.pixels[xp][yp] ^= plane_mask;
+                }
+            }
+            offset += bytes_per_plane;
+        }
+
+        collision
+    }
+
+    // Moves the selected planes by the given offset, uncovered pixels are cleared
+    fn shift(&mut self, dx: isize, dy: isize) {
+        self.needs_redraw = true;
+        let (width, height) = (self.get_width() as isize, self.get_height() as isize);
+        let source = self.pixels;
+
+        for x in 0..width {
+            for y in 0..height {
+                let (source_x, source_y) = (x - dx, y - dy);
+                let shifted =
+                    if source_x >= 0 && source_x < width && source_y >= 0 && source_y < height {
+                        source[source_x as usize][source_y as usize] & self.selected_planes
+                    } else {
+                        0
+                    };
+
+                let pixel = &mut self.pixels[x as usize][y as usize];
+                *pixel = (*pixel & !self.selected_planes) | shifted;
+            }
         }
     }
 
@@ -1,14 +1,17 @@
-extern crate sdl2;
-
-use sdl2::video::Window;
-use std::io::{Error, Read};
+use std::io::{Error, Read, Write};
 
+use crate::checksum::crc32;
 use crate::cpu::*;
 use crate::display::*;
+use crate::error::EmulatorError;
 use crate::keypad::*;
 use crate::memory::*;
+use crate::quirks::Quirks;
+use crate::renderer::RendererTrait;
+use crate::savestate::{StateReader, StateWriter};
 use crate::speaker::*;
 
+/// The complete machine: a CPU with its memory, keypad, display and speaker, run in 60 Hz frames
 pub struct Emulator {
     pub cpu: Cpu,
     pub memory: Memory,
@@ -17,70 +20,279 @@ pub struct Emulator {
     pub speaker: Speaker,
 
     iteration: u64,
+
+    // Frame scheduler, the CPU runs a batch of cycles per 60 Hz frame
+    frame: u64,             // number of the current frame
+    frame_cycles: u64,      // cycles of the current frame
+    executed_cycles: u64,   // cycles executed in the current frame
+    cycle_accumulator: f64, // fractional cycles that are carried over to the next frame
 }
 
 impl Emulator {
     // Constructors
+    /// Creates an emulator without a program, clock_rate is the number of instructions per second
     pub fn new(
         clock_rate: f32,
         ignore_unknown_instructions: bool,
         program_address: usize,
-        display_scale: u8,
+        mode: Mode,
+        quirks: Quirks,
     ) -> Emulator {
-        Emulator {
-            cpu: Cpu::new(clock_rate, ignore_unknown_instructions, program_address),
-            memory: Memory::new(),
+        let mut emulator = Emulator {
+            cpu: Cpu::new(
+                clock_rate,
+                ignore_unknown_instructions,
+                program_address,
+                mode,
+                quirks,
+            ),
+            memory: Memory::new(match mode {
+                Mode::XoChip => XO_CHIP_MEMORY_SIZE,
+                _ => MEMORY_SIZE,
+            }),
             keypad: Keypad::new(),
-            display: Display::new(display_scale),
+            display: Display::new(quirks),
             speaker: Speaker::new(),
 
             iteration: 1,
-        }
+
+            // Frame scheduler
+            frame: 0,
+            frame_cycles: 0,
+            executed_cycles: 0,
+            cycle_accumulator: 0.0,
+        };
+        emulator.start_frame();
+        emulator
     }
 
     // Methods
-    pub fn load_rom(&mut self, reader: &mut dyn Read) -> Result<usize, Error> {
+    /// Resets the devices and loads a program, returns the size of the ROM in bytes
+    pub fn load_rom(&mut self, reader: &mut dyn Read) -> Result<usize, EmulatorError> {
         self.iteration = 1;
         self.keypad.reset();
-        self.display.clear();
-        self.speaker.clear_queue();
+        self.display.reset();
+        self.speaker.reset();
+
+        self.frame = 0;
+        self.cycle_accumulator = 0.0;
+        self.start_frame();
 
         self.cpu.load_rom(&mut self.memory, reader)
     }
 
-    pub fn step(
-        &mut self,
-        delta_time: f32,
-        mut renderer: &mut sdl2::render::Canvas<Window>,
-        sound: bool,
-        debug_cpu: bool,
-        debug_memory: bool,
-    ) {
+    /// Writes the complete emulator state, it can be restored with load_state
+    pub fn save_state(&self, writer: &mut dyn Write) -> Result<(), Error> {
+        let mut state = StateWriter::new();
+        state.write_u64(self.iteration);
+        state.write_u64(self.frame);
+        state.write_u64(self.frame_cycles);
+        state.write_u64(self.executed_cycles);
+        state.write_u64(self.cycle_accumulator.to_bits());
+        self.cpu.save_state(&mut state);
+        self.memory.save_state(&mut state);
+        self.keypad.save_state(&mut state);
+        self.display.save_state(&mut state);
+        self.speaker.save_state(&mut state);
+
+        state.finish(writer)
+    }
+
+    /// The emulator is left unchanged if the save state cannot be loaded
+    pub fn load_state(&mut self, reader: &mut dyn Read) -> Result<(), Error> {
+        let mut state = StateReader::new(reader)?;
+
+        let mut backup = Vec::new();
+        self.save_state(&mut backup)?;
+        if let Err(error) = self.read_state(&mut state) {
+            self.read_state(&mut StateReader::new(&mut backup.as_slice())?)?;
+            return Err(error);
+        }
+
+        Ok(())
+    }
+
+    /// Checksum of the complete emulator state, used to detect desyncs
+    pub fn get_checksum(&self) -> u32 {
+        let mut state = Vec::new();
+        self.save_state(&mut state).unwrap();
+        crc32(&state)
+    }
+
+    fn read_state(&mut self, state: &mut StateReader) -> Result<(), Error> {
+        self.iteration = state.read_u64()?;
+        self.frame = state.read_u64()?;
+        self.frame_cycles = state.read_u64()?;
+        self.executed_cycles = state.read_u64()?;
+        self.cycle_accumulator = f64::from_bits(state.read_u64()?);
+        self.cpu.load_state(state)?;
+        self.memory.load_state(state)?;
+        self.keypad.load_state(state)?;
+        self.display.load_state(state)?;
+        self.speaker.load_state(state)?;
+        self.speaker.set_tone(self.cpu.get_sound_timer() > 0);
+
+        Ok(())
+    }
+
+    /// Executes one instruction, the timers are updated after the last instruction of a frame.
+    /// A faulting instruction is not executed, the emulator stays at its program counter.
+    pub fn step(&mut self, debug_cpu: bool, debug_memory: bool) -> Result<(), EmulatorError> {
         // Debugging
         if debug_cpu || debug_memory {
             println!("\nIteration #{}", self.iteration);
         }
 
+        // Queued input only changes the keypad at the start of a frame
+        if self.executed_cycles == 0 {
+            self.keypad.update(self.frame);
+        }
+
         // CPU
         self.cpu.step(
-            delta_time,
             &mut self.memory,
             &mut self.keypad,
             &mut self.display,
             &mut self.speaker,
             debug_cpu,
             debug_memory,
-        );
+        )?;
+        self.iteration += 1;
 
-        // Other devices
-        if self.display.needs_redraw() || self.iteration == 1 {
-            self.display.draw(&mut renderer);
+        self.executed_cycles += 1;
+        if self.executed_cycles >= self.frame_cycles {
+            self.end_frame();
         }
 
-        if sound {
-            self.speaker.flush_queue();
+        Ok(())
+    }
+
+    /// Executes the remaining instructions of the current frame
+    pub fn run_frame(&mut self, debug_cpu: bool, debug_memory: bool) -> Result<(), EmulatorError> {
+        let frame = self.frame;
+        while self.frame == frame && !self.cpu.has_exited() {
+            self.step(debug_cpu, debug_memory)?;
         }
 
-        self.iteration += 1;
+        Ok(())
+    }
+
+    /// Returns the number of the 60 Hz frame if the next step starts a new one
+    pub fn get_frame_start(&self) -> Option<u64> {
+        if self.executed_cycles == 0 {
+            Some(self.frame)
+        } else {
+            None
+        }
+    }
+
+    /// Number of the current 60 Hz frame, counted since the ROM was loaded
+    pub fn get_frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Number of the next instruction, counted since the ROM was loaded
+    pub fn get_iteration(&self) -> u64 {
+        self.iteration
+    }
+
+    fn end_frame(&mut self) {
+        // Frames without cycles only occur at clock rates below 60 Hz
+        loop {
+            self.cpu.end_frame(&mut self.speaker);
+            self.speaker.end_frame();
+
+            self.frame += 1;
+            self.start_frame();
+            if self.frame_cycles > 0 {
+                break;
+            }
+        }
+    }
+
+    fn start_frame(&mut self) {
+        self.cycle_accumulator += self.cpu.get_clock_rate() as f64 / 60.0;
+        self.frame_cycles = self.cycle_accumulator.floor() as u64;
+        self.cycle_accumulator -= self.frame_cycles as f64;
+        self.executed_cycles = 0;
+    }
+
+    /// Draws the display if it changed since the last call
+    pub fn render(&mut self, renderer: &mut dyn RendererTrait) {
+        if self.display.needs_redraw() {
+            renderer.render(&self.display);
+            self.display.set_redrawn();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instantiate_emulator(clock_rate: f32, program: &[u16]) -> Emulator {
+        let mut emulator = Emulator::new(clock_rate, false, 0x200, Mode::Chip8, Quirks::default());
+        let rom: Vec<u8> = program
+            .iter()
+            .flat_map(|opcode| opcode.to_be_bytes())
+            .collect();
+        emulator.load_rom(&mut rom.as_slice()).unwrap();
+        emulator
+    }
+
+    #[test]
+    fn test_fractional_cycles_carry_over() {
+        // ADD V0, 1 counts the executed cycles
+        let mut emulator = instantiate_emulator(500.0, &[0x7001; 64]);
+
+        let mut executed = Vec::new();
+        for _ in 0..3 {
+            emulator.run_frame(false, false).unwrap();
+            executed.push(emulator.cpu.get_register(0x0));
+        }
+
+        assert_eq!(executed, vec![8, 16, 25]);
+        assert_eq!(emulator.get_frame_start(), Some(3));
+    }
+
+    #[test]
+    fn test_timers_decrement_once_per_frame() {
+        // LD V1, 5; LD DT, V1; LD ST, V1
+        let mut program = vec![0x6105, 0xF115, 0xF118];
+        program.extend_from_slice(&[0x7001; 64]);
+        let mut emulator = instantiate_emulator(600.0, &program);
+
+        emulator.run_frame(false, false).unwrap();
+        assert_eq!(emulator.cpu.get_delay_timer(), 4);
+        assert_eq!(emulator.cpu.get_sound_timer(), 4);
+
+        emulator.step(false, false).unwrap();
+        assert_eq!(emulator.cpu.get_delay_timer(), 4);
+        assert_eq!(emulator.get_frame_start(), None);
+
+        emulator.run_frame(false, false).unwrap();
+        assert_eq!(emulator.cpu.get_delay_timer(), 3);
+        assert_eq!(emulator.get_frame_start(), Some(2));
+    }
+
+    #[test]
+    fn test_queued_tap_lasts_one_frame() {
+        // SKNP V0; ADD V1, 1; JP 0x200 counts the cycles key 0 is pressed
+        let mut emulator = instantiate_emulator(600.0, &[0xE0A1, 0x7101, 0x1200]);
+        for (pressed, time) in [(true, 0), (false, 1)].iter() {
+            emulator.keypad.queue_event(InputEvent {
+                host_key: "X".to_string(),
+                pressed: *pressed,
+                time: *time,
+            });
+        }
+
+        emulator.run_frame(false, false).unwrap();
+        assert_eq!(emulator.cpu.get_register(0x1), 3);
+
+        // The first instruction of the next frame still completes the last check
+        emulator.run_frame(false, false).unwrap();
+        emulator.run_frame(false, false).unwrap();
+        assert_eq!(emulator.cpu.get_register(0x1), 4);
     }
 }
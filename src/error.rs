@@ -0,0 +1,86 @@
+use std::fmt;
+use std::io;
+
+// Errors of loading and running a ROM, CPU faults contain the opcode and the program counter
+#[derive(Debug)]
+pub enum EmulatorError {
+    Io(io::Error),
+    EmptyRom,
+    RomTooLarge {
+        rom_size: usize,
+        available_memory: usize,
+    },
+    FontTooLarge {
+        font_size: usize,
+        memory_size: usize,
+    },
+    UnknownInstruction {
+        opcode: u16,
+        pc: usize,
+    },
+    StackOverflow {
+        opcode: u16,
+        pc: usize,
+    },
+    StackUnderflow {
+        opcode: u16,
+        pc: usize,
+    },
+    AddressOutOfRange {
+        address: usize,
+        pc: usize,
+    },
+}
+
+impl fmt::Display for EmulatorError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EmulatorError::Io(error) => write!(formatter, "{}", error),
+            EmulatorError::EmptyRom => write!(formatter, "ROM does not contain any instructions"),
+            EmulatorError::RomTooLarge {
+                rom_size,
+                available_memory,
+            } => write!(
+                formatter,
+                "ROM size ({} bytes) is larger than available program memory ({} bytes)",
+                rom_size, available_memory
+            ),
+            EmulatorError::FontTooLarge {
+                font_size,
+                memory_size,
+            } => write!(
+                formatter,
+                "Font size ({} bytes) is larger than available memory ({} bytes)",
+                font_size, memory_size
+            ),
+            EmulatorError::UnknownInstruction { opcode, pc } => write!(
+                formatter,
+                "Unknown instruction {:04X} at 0x{:03X}",
+                opcode, pc
+            ),
+            EmulatorError::StackOverflow { opcode, pc } => write!(
+                formatter,
+                "Stack overflow by instruction {:04X} at 0x{:03X}",
+                opcode, pc
+            ),
+            EmulatorError::StackUnderflow { opcode, pc } => write!(
+                formatter,
+                "Stack underflow by instruction {:04X} at 0x{:03X}",
+                opcode, pc
+            ),
+            EmulatorError::AddressOutOfRange { address, pc } => write!(
+                formatter,
+                "Address 0x{:03X} is out of memory at 0x{:03X}",
+                address, pc
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EmulatorError {}
+
+impl From<io::Error> for EmulatorError {
+    fn from(error: io::Error) -> EmulatorError {
+        EmulatorError::Io(error)
+    }
+}
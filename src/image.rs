@@ -0,0 +1,217 @@
+use std::io::{Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use crate::checksum::{adler32, crc32_update};
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+const DEFLATE_MAX_BLOCK_BYTES: usize = 0xFFFF;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageFormat {
+    Pbm, // Monochrome, every pixel with a set plane is black
+    Ppm,
+    Png,
+}
+
+impl ImageFormat {
+    // Determines the format from the file extension
+    pub fn from_path(path: &Path) -> Option<ImageFormat> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "pbm" => Some(ImageFormat::Pbm),
+            "ppm" => Some(ImageFormat::Ppm),
+            "png" => Some(ImageFormat::Png),
+            _ => None,
+        }
+    }
+}
+
+// Screenshots are stored next to the ROM using the first free number, e.g. "pong.screenshot-2.png"
+pub fn get_screenshot_path(rom: &Path) -> PathBuf {
+    get_free_path(rom, "screenshot", "png")
+}
+
+pub fn get_free_path(rom: &Path, kind: &str, extension: &str) -> PathBuf {
+    let mut number = 0;
+    loop {
+        let path = rom.with_extension(format!(
+            "{kind}-{number}.{extension}",
+            kind = kind,
+            number = number,
+            extension = extension
+        ));
+        if !path.exists() {
+            return path;
+        }
+        number += 1;
+    }
+}
+
+// Encodes an image consisting of palette indices, stored row by row
+pub fn write_image(
+    writer: &mut dyn Write,
+    format: ImageFormat,
+    width: usize,
+    height: usize,
+    pixels: &[u8],
+    palette: &[(u8, u8, u8)],
+) -> Result<(), Error> {
+    if pixels.len() != width * height {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Pixel count does not match the image size",
+        ));
+    }
+
+    match format {
+        ImageFormat::Pbm => write_pbm(writer, width, height, pixels),
+        ImageFormat::Ppm => write_ppm(writer, width, height, &to_rgb(pixels, palette)),
+        ImageFormat::Png => write_png(writer, width, height, &to_rgb(pixels, palette)),
+    }
+}
+
+pub fn to_rgb(pixels: &[u8], palette: &[(u8, u8, u8)]) -> Vec<u8> {
+    let mut rgb = Vec::with_capacity(pixels.len() * 3);
+    for pixel in pixels {
+        let (red, green, blue) = palette[*pixel as usize];
+        rgb.extend_from_slice(&[red, green, blue]);
+    }
+    rgb
+}
+
+// Binary PBM (P4), rows are padded to full bytes
+fn write_pbm(
+    writer: &mut dyn Write,
+    width: usize,
+    height: usize,
+    pixels: &[u8],
+) -> Result<(), Error> {
+    write!(writer, "P4\n{} {}\n", width, height)?;
+
+    for row in pixels.chunks(width) {
+        let mut bytes = vec![0; (width + 7) / 8];
+        for (x, pixel) in row.iter().enumerate() {
+            if *pixel != 0 {
+                bytes[x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+        writer.write_all(&bytes)?;
+    }
+
+    Ok(())
+}
+
+// Binary PPM (P6)
+fn write_ppm(writer: &mut dyn Write, width: usize, height: usize, rgb: &[u8]) -> Result<(), Error> {
+    write!(writer, "P6\n{} {}\n255\n", width, height)?;
+    writer.write_all(rgb)
+}
+
+// 8 bit RGB PNG. The image data is stored uncompressed, which avoids a deflate implementation.
+fn write_png(writer: &mut dyn Write, width: usize, height: usize, rgb: &[u8]) -> Result<(), Error> {
+    writer.write_all(&PNG_SIGNATURE)?;
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    header.extend_from_slice(&[8, 2, 0, 0, 0]); // Bit depth, color type, compression, filter, interlace
+    write_png_chunk(writer, b"IHDR", &header)?;
+
+    // Every scanline starts with its filter type (none)
+    let mut scanlines = Vec::with_capacity(height * (width * 3 + 1));
+    for row in rgb.chunks(width * 3) {
+        scanlines.push(0);
+        scanlines.extend_from_slice(row);
+    }
+
+    // zlib stream with stored deflate blocks
+    let mut data = vec![0x78, 0x01];
+    let blocks = scanlines.chunks(DEFLATE_MAX_BLOCK_BYTES);
+    let block_count = blocks.len();
+    for (index, block) in blocks.enumerate() {
+        data.push((index + 1 == block_count) as u8);
+        data.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        data.extend_from_slice(block);
+    }
+    data.extend_from_slice(&adler32(&scanlines).to_be_bytes());
+    write_png_chunk(writer, b"IDAT", &data)?;
+
+    write_png_chunk(writer, b"IEND", &[])
+}
+
+fn write_png_chunk(writer: &mut dyn Write, kind: &[u8; 4], data: &[u8]) -> Result<(), Error> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+    writer.write_all(&crc32_update(crc32_update(0, kind), data).to_be_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_PALETTE: [(u8, u8, u8); 2] = [(0, 0, 0), (255, 255, 255)];
+
+    fn encode(format: ImageFormat, width: usize, height: usize, pixels: &[u8]) -> Vec<u8> {
+        let mut image = Vec::new();
+        write_image(&mut image, format, width, height, pixels, &TEST_PALETTE).unwrap();
+        image
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(
+            ImageFormat::from_path(Path::new("pong.PNG")),
+            Some(ImageFormat::Png)
+        );
+        assert_eq!(
+            ImageFormat::from_path(Path::new("pong.ppm")),
+            Some(ImageFormat::Ppm)
+        );
+        assert_eq!(ImageFormat::from_path(Path::new("pong.bmp")), None);
+    }
+
+    #[test]
+    fn test_write_pbm() {
+        let mut pixels = vec![0; 9 * 2];
+        pixels[0] = 1;
+        pixels[8] = 1;
+        pixels[9 + 1] = 1;
+
+        let mut expected = b"P4\n9 2\n".to_vec();
+        expected.extend_from_slice(&[0b1000_0000, 0b1000_0000, 0b0100_0000, 0b0000_0000]);
+        assert_eq!(encode(ImageFormat::Pbm, 9, 2, &pixels), expected);
+    }
+
+    #[test]
+    fn test_write_ppm() {
+        let mut expected = b"P6\n2 1\n255\n".to_vec();
+        expected.extend_from_slice(&[255, 255, 255, 0, 0, 0]);
+        assert_eq!(encode(ImageFormat::Ppm, 2, 1, &[1, 0]), expected);
+    }
+
+    #[test]
+    fn test_write_png() {
+        let image = encode(ImageFormat::Png, 1, 1, &[1]);
+
+        let mut expected = PNG_SIGNATURE.to_vec();
+        expected.extend_from_slice(&[
+            0x00, 0x00, 0x00, 0x0D, b'I', b'H', b'D', b'R', // IHDR
+            0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x08, 0x02, 0x00, 0x00, 0x00, 0x90,
+            0x77, 0x53, 0xDE, // CRC
+            0x00, 0x00, 0x00, 0x0F, b'I', b'D', b'A', b'T', // IDAT
+            0x78, 0x01, 0x01, 0x04, 0x00, 0xFB, 0xFF, 0x00, 0xFF, 0xFF, 0xFF, 0x05, 0xFE, 0x02,
+            0xFE, 0x49, 0x66, 0x6E, 0x2B, // Adler-32 and CRC
+            0x00, 0x00, 0x00, 0x00, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82, // IEND
+        ]);
+        assert_eq!(image, expected);
+    }
+
+    #[test]
+    fn test_write_image_rejects_wrong_size() {
+        let mut image = Vec::new();
+        let result = write_image(&mut image, ImageFormat::Ppm, 2, 2, &[0], &TEST_PALETTE);
+
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+}
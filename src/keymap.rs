@@ -0,0 +1,326 @@
+use std::collections::HashMap;
+
+pub const PRESETS: [&str; 4] = ["qwerty", "qwertz", "azerty", "numpad"];
+
+// Hexadecimal keys of the COSMAC VIP keypad, row by row
+const KEYPAD_LAYOUT: [u8; 16] = [
+    0x1, 0x2, 0x3, 0xC, //
+    0x4, 0x5, 0x6, 0xD, //
+    0x7, 0x8, 0x9, 0xE, //
+    0xA, 0x0, 0xB, 0xF,
+];
+
+// Controller buttons are named "pad:" followed by the SDL button name
+pub const CONTROLLER_PREFIX: &str = "pad:";
+
+// Default controller layout: the D-pad on the arrow keys 2/4/6/8 used by most games, the face
+// buttons and shoulders on the remaining keys near them
+const CONTROLLER_BINDINGS: [(&str, u8); 10] = [
+    ("pad:dpup", 0x2),
+    ("pad:dpleft", 0x4),
+    ("pad:dpright", 0x6),
+    ("pad:dpdown", 0x8),
+    ("pad:a", 0x5),
+    ("pad:b", 0x0),
+    ("pad:x", 0x1),
+    ("pad:y", 0x3),
+    ("pad:leftshoulder", 0x7),
+    ("pad:rightshoulder", 0x9),
+];
+
+// Controller bindings of the bundled games, by ROM file names
+const PONG_PROFILE: [(&str, u8); 2] = [("pad:dpup", 0x1), ("pad:dpdown", 0x4)];
+const INVADERS_PROFILE: [(&str, u8); 3] =
+    [("pad:dpleft", 0x4), ("pad:dpright", 0x6), ("pad:a", 0x5)];
+const CONTROLLER_PROFILES: [(&[&str], &[(&str, u8)]); 2] = [
+    (&["pong", "pong2"], &PONG_PROFILE),
+    (
+        &["invaders", "space_invaders", "space_invaders2"],
+        &INVADERS_PROFILE,
+    ),
+];
+
+// How the frontend names host keys
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyMode {
+    Keycode,  // by the character of the key in the keyboard layout of the OS
+    Scancode, // by the physical position, named as on a US keyboard
+}
+
+impl KeyMode {
+    pub fn from_name(name: &str) -> Option<KeyMode> {
+        match name {
+            "keycode" => Some(KeyMode::Keycode),
+            "scancode" => Some(KeyMode::Scancode),
+            _ => None,
+        }
+    }
+}
+
+// Maps host keys to the 16 hexadecimal keys. Host keys are identified by their SDL key name
+// (e.g. "Q", "Keypad 7", "Up") and controller buttons as "pad:dpup", "pad:a", "pad:leftshoulder",
+// compared case-insensitively. Several host keys can be bound to
+// the same hexadecimal key. With scancodes, the qwerty preset always covers the 1234/QWER/ASDF/ZXCV
+// block, independent of the keyboard layout.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyMap {
+    bindings: HashMap<String, u8>,
+}
+
+impl KeyMap {
+    pub fn from_preset(name: &str) -> Option<KeyMap> {
+        // Host keys in the order of the keypad layout
+        let host_keys = match name {
+            "qwerty" => [
+                "1", "2", "3", "4", "Q", "W", "E", "R", "A", "S", "D", "F", "Z", "X", "C", "V",
+            ],
+            "qwertz" => [
+                "1", "2", "3", "4", "Q", "W", "E", "R", "A", "S", "D", "F", "Y", "X", "C", "V",
+            ],
+            "azerty" => [
+                "&", "É", "\"", "'", "A", "Z", "E", "R", "Q", "S", "D", "F", "W", "X", "C", "V",
+            ],
+            // The digits keep their value, the operators are used for A-F
+            "numpad" => {
+                return Some(KeyMap::from_bindings(&[
+                    ("Keypad 0", 0x0),
+                    ("Keypad 1", 0x1),
+                    ("Keypad 2", 0x2),
+                    ("Keypad 3", 0x3),
+                    ("Keypad 4", 0x4),
+                    ("Keypad 5", 0x5),
+                    ("Keypad 6", 0x6),
+                    ("Keypad 7", 0x7),
+                    ("Keypad 8", 0x8),
+                    ("Keypad 9", 0x9),
+                    ("Keypad /", 0xA),
+                    ("Keypad *", 0xB),
+                    ("Keypad -", 0xC),
+                    ("Keypad +", 0xD),
+                    ("Keypad Enter", 0xE),
+                    ("Keypad .", 0xF),
+                ]))
+            }
+            _ => return None,
+        };
+
+        let bindings: Vec<(&str, u8)> = host_keys
+            .iter()
+            .zip(KEYPAD_LAYOUT.iter())
+            .map(|(host_key, key)| (*host_key, *key))
+            .collect();
+        Some(KeyMap::from_bindings(&bindings))
+    }
+
+    pub fn from_bindings(bindings: &[(&str, u8)]) -> KeyMap {
+        let mut keymap = KeyMap {
+            bindings: HashMap::new(),
+        };
+        for (host_key, key) in bindings {
+            keymap.bind(host_key, *key);
+        }
+        keymap
+    }
+
+    // Applies a config file to the keymap. Every line binds a hexadecimal key to a comma separated
+    // list of host keys. It replaces the previous bindings of the same kind, keyboard keys or
+    // controller buttons, so remapping the keyboard keeps the controller bindings and vice versa:
+    //
+    //   # Comment
+    //   5 = W, Up
+    //   [pong]      (the following lines only apply to ROMs with the file name pong.*)
+    //   1 = Up
+    pub fn apply_config(&mut self, config: &str, rom_name: &str) -> Result<(), String> {
+        let mut active = true;
+        for (index, line) in config.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: &str| format!("line {}: {}", index + 1, message);
+
+            if line.starts_with('[') && line.ends_with(']') {
+                active = line[1..line.len() - 1]
+                    .trim()
+                    .eq_ignore_ascii_case(rom_name);
+                continue;
+            }
+
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap().trim();
+            let host_keys = parts
+                .next()
+                .ok_or_else(|| error("Expected \"key = host keys\""))?;
+            let key = u8::from_str_radix(key, 16)
+                .ok()
+                .filter(|key| *key < 16)
+                .ok_or_else(|| error(&format!("Unknown key \"{}\"", key)))?;
+
+            if active {
+                let host_keys: Vec<&str> = host_keys
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|host_key| !host_key.is_empty())
+                    .collect();
+                let controller = host_keys
+                    .iter()
+                    .any(|host_key| is_controller_button(host_key));
+                let keyboard = host_keys
+                    .iter()
+                    .any(|host_key| !is_controller_button(host_key));
+
+                // Without host keys, the key is unbound completely
+                self.bindings.retain(|host_key, bound_key| {
+                    let replaced = host_keys.is_empty()
+                        || if is_controller_button(host_key) {
+                            controller
+                        } else {
+                            keyboard
+                        };
+                    *bound_key != key || !replaced
+                });
+                for host_key in host_keys {
+                    self.bind(host_key, key);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Adds the default controller layout and the profile of the ROM, if there is one
+    pub fn add_controller_bindings(&mut self, rom_name: &str) {
+        for (button, key) in CONTROLLER_BINDINGS.iter() {
+            self.bind(button, *key);
+        }
+
+        for (button, key) in get_controller_profile(rom_name).iter() {
+            self.bind(button, *key);
+        }
+    }
+
+    pub fn get_key(&self, host_key: &str) -> Option<u8> {
+        self.bindings.get(&host_key.to_lowercase()).cloned()
+    }
+
+    fn bind(&mut self, host_key: &str, key: u8) {
+        self.bindings.insert(host_key.to_lowercase(), key);
+    }
+}
+
+fn is_controller_button(host_key: &str) -> bool {
+    host_key.to_lowercase().starts_with(CONTROLLER_PREFIX)
+}
+
+fn get_controller_profile(rom_name: &str) -> &'static [(&'static str, u8)] {
+    CONTROLLER_PROFILES
+        .iter()
+        .find(|(names, _)| names.iter().any(|name| name.eq_ignore_ascii_case(rom_name)))
+        .map_or(&[], |(_, bindings)| *bindings)
+}
+
+impl Default for KeyMap {
+    fn default() -> KeyMap {
+        KeyMap::from_preset("qwerty").unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presets() {
+        for preset in PRESETS.iter() {
+            let keymap = KeyMap::from_preset(preset).unwrap();
+            let mut keys: Vec<u8> = keymap.bindings.values().cloned().collect();
+            keys.sort();
+            assert_eq!(keys, (0..16).collect::<Vec<u8>>(), "{}", preset);
+        }
+
+        assert_eq!(KeyMap::default().get_key("z"), Some(0xA));
+        assert_eq!(
+            KeyMap::from_preset("qwertz").unwrap().get_key("Y"),
+            Some(0xA)
+        );
+        assert_eq!(
+            KeyMap::from_preset("azerty").unwrap().get_key("é"),
+            Some(0x2)
+        );
+        assert_eq!(KeyMap::from_preset("dvorak"), None);
+    }
+
+    #[test]
+    fn test_apply_config() {
+        let config = "# Arrow keys\n5 = W, Up\n\n[pong]\n1 = Up\n4 = Down\n[tetris]\n1 = Space\n";
+        let mut keymap = KeyMap::default();
+
+        keymap.apply_config(config, "pong").unwrap();
+
+        assert_eq!(keymap.get_key("W"), Some(0x5));
+        assert_eq!(keymap.get_key("Up"), Some(0x1));
+        assert_eq!(keymap.get_key("Down"), Some(0x4));
+        assert_eq!(keymap.get_key("Q"), None);
+        assert_eq!(keymap.get_key("1"), None);
+        assert_eq!(keymap.get_key("Space"), None);
+    }
+
+    #[test]
+    fn test_controller_bindings() {
+        let mut keymap = KeyMap::default();
+        keymap.add_controller_bindings("pong");
+
+        assert_eq!(keymap.get_key("pad:dpup"), Some(0x1));
+        assert_eq!(keymap.get_key("pad:dpdown"), Some(0x4));
+        assert_eq!(keymap.get_key("pad:a"), Some(0x5));
+        assert_eq!(keymap.get_key("Q"), Some(0x4));
+
+        // Profiles can be changed in the per-ROM sections of the config file, keyboard and
+        // controller bindings are replaced independently
+        keymap
+            .apply_config("[pong]\nC = pad:y\n4 = Down\n", "pong")
+            .unwrap();
+        assert_eq!(keymap.get_key("pad:y"), Some(0xC));
+        assert_eq!(keymap.get_key("4"), Some(0xC));
+        assert_eq!(keymap.get_key("Down"), Some(0x4));
+        assert_eq!(keymap.get_key("Q"), None);
+        assert_eq!(keymap.get_key("pad:dpdown"), Some(0x4));
+
+        // A line without host keys unbinds the key completely
+        keymap.apply_config("4 =", "pong").unwrap();
+        assert_eq!(keymap.get_key("Down"), None);
+        assert_eq!(keymap.get_key("pad:dpdown"), None);
+
+        let mut keymap = KeyMap::default();
+        keymap.add_controller_bindings("maze");
+        assert_eq!(keymap.get_key("pad:dpup"), Some(0x2));
+    }
+
+    #[test]
+    fn test_controller_profiles() {
+        for rom_name in ["invaders", "space_invaders", "Space_Invaders2"].iter() {
+            assert_eq!(
+                get_controller_profile(rom_name),
+                &INVADERS_PROFILE,
+                "{}",
+                rom_name
+            );
+        }
+        assert_eq!(get_controller_profile("pong2"), &PONG_PROFILE);
+        assert!(get_controller_profile("maze").is_empty());
+    }
+
+    #[test]
+    fn test_config_errors() {
+        let mut keymap = KeyMap::default();
+
+        assert_eq!(
+            keymap.apply_config("5 W", "pong"),
+            Err("line 1: Expected \"key = host keys\"".to_string())
+        );
+        assert_eq!(
+            keymap.apply_config("\nG = W", "pong"),
+            Err("line 2: Unknown key \"G\"".to_string())
+        );
+    }
+}
@@ -1,18 +1,42 @@
-use sdl2::keyboard::Keycode;
+use std::collections::{HashMap, VecDeque};
+use std::io::Error;
+
+use crate::keymap::KeyMap;
+use crate::savestate::{StateReader, StateWriter};
 
 #[cfg(test)]
 use mockall::{automock, predicate::*};
 
+/// Hexadecimal keypad with the keys 0x0 to 0xF
 #[cfg_attr(test, automock)]
 pub trait KeypadTrait {
     fn get_key(&mut self, key: u8) -> bool;
-    fn key_down(&mut self, keycode: Keycode);
-    fn key_up(&mut self, keycode: Keycode);
+    fn set_key(&mut self, key: u8, pressed: bool);
+    /// Releases all keys
     fn reset(&mut self);
 }
 
+/// Input of the frontend, timestamped in milliseconds of the host
+#[derive(Clone, Debug, PartialEq)]
+pub struct InputEvent {
+    pub host_key: String,
+    pub pressed: bool,
+    pub time: u32,
+}
+
+/// Keypad of the emulator, host keys of the frontend are translated by a key map
 pub struct Keypad {
     keys: [bool; 16], // 16 hexadecimal keys (0-9 and A-F)
+    held_host_keys: Vec<String>,
+
+    // Input queue, applied at the start of the emulated frames
+    events: VecDeque<InputEvent>,
+    press_times: HashMap<String, (u64, u32)>, // frame and host time of queued presses
+    updated_frame: Option<u64>,
+
+    // Configuration
+    keymap: KeyMap,
+    min_hold: u64, // frames a queued key press lasts at least
 }
 
 impl KeypadTrait for Keypad {
@@ -20,55 +44,239 @@ impl KeypadTrait for Keypad {
         self.keys[key as usize]
     }
 
-    fn key_down(&mut self, keycode: Keycode) {
-        let key = Keypad::map_key(keycode);
-        if key.is_some() {
-            self.keys[key.unwrap() as usize] = true;
+    // Presses or releases a key directly, e.g. for scripted input
+    fn set_key(&mut self, key: u8, pressed: bool) {
+        self.keys[key as usize] = pressed;
+    }
+
+    fn reset(&mut self) {
+        self.keys = [false; 16];
+        self.held_host_keys.clear();
+        self.events.clear();
+        self.press_times.clear();
+        self.updated_frame = None;
+    }
+}
+
+impl Keypad {
+    /// Creates a keypad with the default key map and all keys released
+    pub fn new() -> Keypad {
+        Keypad {
+            keys: [false; 16],
+            held_host_keys: Vec::new(),
+
+            // Input queue
+            events: VecDeque::new(),
+            press_times: HashMap::new(),
+            updated_frame: None,
+
+            // Configuration
+            keymap: KeyMap::default(),
+            min_hold: 1,
         }
     }
 
-    fn key_up(&mut self, keycode: Keycode) {
-        let key = Keypad::map_key(keycode);
-        if key.is_some() {
-            self.keys[key.unwrap() as usize] = false;
+    /// Replaces the key map, held host keys are released
+    pub fn set_keymap(&mut self, keymap: KeyMap) {
+        self.keymap = keymap;
+        self.held_host_keys.clear();
+    }
+
+    /// Whether the key map assigns the host key to a key
+    pub fn is_bound(&self, host_key: &str) -> bool {
+        self.keymap.get_key(host_key).is_some()
+    }
+
+    /// Number of frames a queued key press lasts at least, so short taps are not missed
+    pub fn set_min_hold(&mut self, frames: u64) {
+        self.min_hold = frames;
+    }
+
+    /// Input of the frontend, applied in emulated time by update. Taps that are shorter than a frame
+    /// or arrive together are not lost, a released key stays pressed for at least the minimum hold
+    /// and for the duration it was held on the host.
+    pub fn queue_event(&mut self, event: InputEvent) {
+        self.events.push_back(event);
+    }
+
+    /// Applies the queued events that are due at the start of the frame, once per frame
+    pub fn update(&mut self, frame: u64) {
+        if self.updated_frame == Some(frame) {
+            return;
+        }
+        self.updated_frame = Some(frame);
+
+        // Every host key changes at most once per frame, so no state is skipped, and events after a
+        // delayed one keep their order
+        let mut blocked_host_keys: Vec<String> = Vec::new();
+        let mut delayed_events = VecDeque::new();
+        while let Some(event) = self.events.pop_front() {
+            let host_key = event.host_key.to_lowercase();
+            if blocked_host_keys.contains(&host_key) || !self.is_due(&host_key, &event, frame) {
+                blocked_host_keys.push(host_key);
+                delayed_events.push_back(event);
+                continue;
+            }
+
+            blocked_host_keys.push(host_key.clone());
+            if event.pressed {
+                self.press_times
+                    .entry(host_key)
+                    .or_insert((frame, event.time));
+                self.host_key_down(&event.host_key);
+            } else {
+                self.press_times.remove(&host_key);
+                self.host_key_up(&event.host_key);
+            }
         }
+        self.events = delayed_events;
     }
 
-    fn reset(&mut self) {
-        self.keys = [false; 16]
+    /// Input of the frontend applied immediately, host keys are named as in the keymap.
+    /// A key stays pressed while any of the host keys bound to it is held.
+    pub fn host_key_down(&mut self, host_key: &str) {
+        if let Some(key) = self.keymap.get_key(host_key) {
+            let host_key = host_key.to_lowercase();
+            if !self.held_host_keys.contains(&host_key) {
+                self.held_host_keys.push(host_key);
+            }
+            self.keys[key as usize] = true;
+        }
+    }
+
+    /// Releases a host key, its key stays pressed while another host key for it is held
+    pub fn host_key_up(&mut self, host_key: &str) {
+        if let Some(key) = self.keymap.get_key(host_key) {
+            let host_key = host_key.to_lowercase();
+            self.held_host_keys.retain(|held| *held != host_key);
+
+            let keymap = &self.keymap;
+            self.keys[key as usize] = self
+                .held_host_keys
+                .iter()
+                .any(|held| keymap.get_key(held) == Some(key));
+        }
+    }
+
+    fn is_due(&self, host_key: &str, event: &InputEvent, frame: u64) -> bool {
+        match self.press_times.get(host_key) {
+            // Frames run backwards while rewinding
+            Some((press_frame, press_time)) if !event.pressed && frame >= *press_frame => {
+                let held_time = event.time.wrapping_sub(*press_time) as u64;
+                let held_frames = (held_time * 60 + 500) / 1000;
+                frame >= press_frame + held_frames.max(self.min_hold)
+            }
+            _ => true,
+        }
+    }
+
+    /// Writes the pressed keys to a save state
+    pub fn save_state(&self, state: &mut StateWriter) {
+        for key in self.keys.iter() {
+            state.write_bool(*key);
+        }
+    }
+
+    /// Only the keys are saved. Held host keys and queued input belong to the frontend and are
+    /// cleared on load, so no key stays stuck and a host key that is still held is released normally.
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), Error> {
+        for key in self.keys.iter_mut() {
+            *key = state.read_bool()?;
+        }
+
+        self.held_host_keys.clear();
+        self.events.clear();
+        self.press_times.clear();
+        self.updated_frame = None;
+
+        Ok(())
     }
 }
 
-impl Keypad {
-    pub fn new() -> Keypad {
-        println!("Initializing keypad");
+/// Pressed keys as bit mask (bit n = key n)
+pub fn get_keys(keypad: &mut dyn KeypadTrait) -> u16 {
+    (0..16).fold(0, |keys, key| keys | (keypad.get_key(key) as u16) << key)
+}
+
+/// Sets all keys from a bit mask, bit n is key n
+pub fn set_keys(keypad: &mut dyn KeypadTrait, keys: u16) {
+    for key in 0..16 {
+        keypad.set_key(key, keys & (1 << key) != 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multiple_host_keys() {
+        let mut keypad = Keypad::new();
+        keypad.set_keymap(KeyMap::from_bindings(&[("W", 0x5), ("Up", 0x5)]));
+
+        keypad.host_key_down("W");
+        keypad.host_key_down("up");
+        keypad.host_key_up("W");
+        assert!(keypad.get_key(0x5));
+
+        keypad.host_key_up("Up");
+        assert!(!keypad.get_key(0x5));
 
-        Keypad { keys: [false; 16] }
+        keypad.host_key_down("Q");
+        assert_eq!(get_keys(&mut keypad), 0);
+        assert!(keypad.is_bound("UP"));
+        assert!(!keypad.is_bound("Q"));
     }
 
-    fn map_key(keycode: Keycode) -> Option<u8> {
-        match keycode {
-            Keycode::Num1 => Some(0x1),
-            Keycode::Num2 => Some(0x2),
-            Keycode::Num3 => Some(0x3),
-            Keycode::Num4 => Some(0xC),
+    fn queue_event(keypad: &mut Keypad, host_key: &str, pressed: bool, time: u32) {
+        keypad.queue_event(InputEvent {
+            host_key: host_key.to_string(),
+            pressed: pressed,
+            time: time,
+        });
+    }
+
+    #[test]
+    fn test_queued_taps() {
+        let mut keypad = Keypad::new();
 
-            Keycode::Q => Some(0x4),
-            Keycode::W => Some(0x5),
-            Keycode::E => Some(0x6),
-            Keycode::R => Some(0xD),
+        // A tap within a frame lasts for one frame
+        queue_event(&mut keypad, "W", true, 1000);
+        queue_event(&mut keypad, "W", false, 1005);
+        keypad.update(10);
+        assert!(keypad.get_key(0x5));
+        keypad.update(11);
+        assert!(!keypad.get_key(0x5));
 
-            Keycode::A => Some(0x7),
-            Keycode::S => Some(0x8),
-            Keycode::D => Some(0x9),
-            Keycode::F => Some(0xE),
+        // Events that arrive together keep the time the key was held on the host
+        queue_event(&mut keypad, "W", true, 2000);
+        queue_event(&mut keypad, "W", false, 2050);
+        queue_event(&mut keypad, "W", true, 2100);
+        queue_event(&mut keypad, "S", true, 2000);
+        keypad.update(12);
+        assert_eq!(get_keys(&mut keypad), 1 << 0x5 | 1 << 0x8);
+        keypad.update(14);
+        assert!(keypad.get_key(0x5));
+        keypad.update(15);
+        assert!(!keypad.get_key(0x5));
+        keypad.update(16);
+        assert!(keypad.get_key(0x5));
+        assert!(keypad.get_key(0x8));
+    }
 
-            Keycode::Y | Keycode::Z => Some(0xA),
-            Keycode::X => Some(0x0),
-            Keycode::C => Some(0xB),
-            Keycode::V => Some(0xF),
+    #[test]
+    fn test_min_hold() {
+        let mut keypad = Keypad::new();
+        keypad.set_min_hold(3);
 
-            _ => None,
+        queue_event(&mut keypad, "W", true, 0);
+        keypad.update(0);
+        queue_event(&mut keypad, "W", false, 20);
+        for frame in 1..3 {
+            keypad.update(frame);
+            assert!(keypad.get_key(0x5), "frame {}", frame);
         }
+        keypad.update(3);
+        assert!(!keypad.get_key(0x5));
     }
 }
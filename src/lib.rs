@@ -0,0 +1,51 @@
+//! Chip-8, SUPER-CHIP and XO-CHIP emulator core.
+//!
+//! An [`Emulator`] combines the [`Cpu`] with its devices ([`Memory`], [`Display`], [`Keypad`],
+//! [`Speaker`]). Each device implements a trait, so the CPU can also be driven with custom devices.
+//! Without the default features `sdl2` and `rodio`, the core pulls in no native libraries:
+//! the display is read with [`Display::get_pixel`] and sound is not played.
+//!
+//! ```no_run
+//! use chip_8_emulator::{Emulator, Mode, Quirks};
+//!
+//! let rom = std::fs::read("roms/games/pong.ch8").unwrap();
+//! let mut emulator = Emulator::new(600.0, false, 0x200, Mode::Chip8, Quirks::default());
+//! emulator.load_rom(&mut rom.as_slice()).unwrap();
+//!
+//! // One second of emulated time
+//! for _ in 0..60 {
+//!     emulator.run_frame(false, false).unwrap();
+//! }
+//! ```
+
+pub mod assembler;
+pub mod checksum;
+pub mod cpu;
+pub mod debugger;
+pub mod disassembler;
+pub mod display;
+pub mod emulator;
+pub mod error;
+pub mod image;
+pub mod keymap;
+pub mod keypad;
+pub mod memory;
+pub mod movie;
+pub mod quirks;
+pub mod random;
+pub mod renderer;
+pub mod rewind;
+pub mod savestate;
+pub mod speaker;
+pub mod speed;
+pub mod video;
+pub mod wav;
+
+pub use crate::cpu::{Cpu, CpuDebugTrait, CpuTrait, Mode};
+pub use crate::display::{Display, DisplayTrait};
+pub use crate::emulator::Emulator;
+pub use crate::error::EmulatorError;
+pub use crate::keypad::{Keypad, KeypadTrait};
+pub use crate::memory::{Memory, MemoryTrait};
+pub use crate::quirks::Quirks;
+pub use crate::speaker::{Speaker, SpeakerTrait};
@@ -2,25 +2,35 @@
 extern crate clap;
 extern crate sdl2;
 
-use clap::App;
+use clap::{App, ArgMatches};
+use sdl2::controller::GameController;
 use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Scancode};
 
-use std::fs::File;
-use std::path::Path;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
 use std::thread;
 use std::time::Duration;
 
-mod cpu;
-mod display;
-mod emulator;
-mod keypad;
-mod memory;
-mod speaker;
-
-use crate::cpu::*;
-use crate::display::*;
-use crate::emulator::Emulator;
-use crate::keypad::*;
+use chip_8_emulator::checksum::crc32;
+use chip_8_emulator::cpu::*;
+use chip_8_emulator::debugger::*;
+use chip_8_emulator::emulator::Emulator;
+use chip_8_emulator::image::*;
+use chip_8_emulator::keymap::{KeyMap, KeyMode, CONTROLLER_PREFIX};
+use chip_8_emulator::keypad::InputEvent;
+use chip_8_emulator::movie::*;
+use chip_8_emulator::quirks::Quirks;
+use chip_8_emulator::renderer::SdlRenderer;
+use chip_8_emulator::rewind::RewindBuffer;
+use chip_8_emulator::savestate::*;
+use chip_8_emulator::speaker::{AudioOutput, ToneConfig, Waveform};
+use chip_8_emulator::speed::Speed;
+use chip_8_emulator::video::*;
+use chip_8_emulator::wav::WavWriter;
+use chip_8_emulator::{assembler, disassembler};
 
 fn main() {
     // Load configuration
@@ -28,38 +38,308 @@ fn main() {
     let yaml = load_yaml!("cli.yml");
     let parameters = App::from_yaml(yaml).get_matches();
 
+    if let Some(parameters) = parameters.subcommand_matches("disasm") {
+        run_disassembler(parameters);
+        return;
+    }
+    if let Some(parameters) = parameters.subcommand_matches("asm") {
+        run_assembler(parameters);
+        return;
+    }
+
     let rom = parameters.value_of("rom").unwrap();
     let clock_rate = value_t!(parameters, "clock_rate", f32).unwrap();
+    let speed = value_t!(parameters, "speed", f32).unwrap();
+    let rewind_memory = value_t!(parameters, "rewind_memory", usize).unwrap();
+    let rewind_interval = value_t!(parameters, "rewind_interval", u64).unwrap();
     let ignore_unknown_instructions = parameters.is_present("ignore_unknown_instructions");
     let program_address = value_t!(parameters, "program_address", usize).unwrap();
+    let mode = Mode::from_name(parameters.value_of("mode").unwrap()).unwrap();
+    let quirks = match parameters.value_of("quirks") {
+        Some("cosmac-vip") => Quirks::COSMAC_VIP,
+        Some("chip-48") => Quirks::CHIP_48,
+        Some("schip") => Quirks::SCHIP,
+        Some("xo-chip") => Quirks::XO_CHIP,
+        _ => match mode {
+            Mode::Chip8 => Quirks::default(),
+            Mode::SuperChip => Quirks::SCHIP,
+            Mode::XoChip => Quirks::XO_CHIP,
+        },
+    };
+    let seed = value_t!(parameters, "seed", u64).unwrap_or_else(|_| rand::random());
     let display_scale = value_t!(parameters, "display_scale", u8).unwrap();
-    let sound = parameters.is_present("sound");
+    let mut keymap = KeyMap::from_preset(parameters.value_of("keys").unwrap()).unwrap();
+    let key_config = parameters.value_of("key_config");
+    let key_mode = KeyMode::from_name(parameters.value_of("key_mode").unwrap()).unwrap();
+    let min_key_hold = value_t!(parameters, "min_key_hold", u64).unwrap();
+    let mute = parameters.is_present("mute");
+    let tone = ToneConfig {
+        frequency: value_t!(parameters, "tone_frequency", f32).unwrap(),
+        waveform: Waveform::from_name(parameters.value_of("waveform").unwrap()).unwrap(),
+        volume: value_t!(parameters, "volume", f32).unwrap() / 100.0,
+    };
+    let headless = parameters.is_present("headless");
+    let cycles = value_t!(parameters, "cycles", u64).ok();
+    let frames = value_t!(parameters, "frames", u64).ok();
+    let screenshot_after = value_t!(parameters, "screenshot_after", u64).ok();
+    let screenshot = match parameters.value_of("screenshot") {
+        Some(screenshot) => PathBuf::from(screenshot),
+        None => Path::new(rom).with_extension("png"),
+    };
     let debug_cpu = parameters.is_present("debug_cpu");
+    let record = parameters.value_of("record");
+    let play = parameters.value_of("play");
+    let wav = parameters.value_of("wav");
+    let record_video = parameters.value_of("record_video").map(PathBuf::from);
     let debug_memory = parameters.is_present("debug_memory");
+    let debugger_enabled = parameters.is_present("debugger");
+    let mut debugger = Debugger::new(debugger_enabled);
 
     if clock_rate <= 0.0 {
         panic!("parameter \"clock_rate\" must be > 0");
     }
+    if speed <= 0.0 {
+        panic!("parameter \"speed\" must be > 0");
+    }
+    if rewind_interval == 0 {
+        panic!("parameter \"rewind_interval\" must be > 0");
+    }
+    if min_key_hold == 0 {
+        panic!("parameter \"min_key_hold\" must be > 0");
+    }
+    if tone.frequency <= 0.0 {
+        panic!("parameter \"tone_frequency\" must be > 0");
+    }
+    if tone.volume < 0.0 || tone.volume > 1.0 {
+        panic!("parameter \"volume\" must be between 0 and 100");
+    }
     if display_scale <= 0 {
         panic!("parameter \"display_scale\" must be > 0");
     }
+    if headless
+        && cycles.is_none()
+        && frames.is_none()
+        && screenshot_after.is_none()
+        && play.is_none()
+    {
+        panic!("parameter \"cycles\", \"frames\", \"screenshot_after\" or \"play\" is required in headless mode");
+    }
+    if ImageFormat::from_path(&screenshot).is_none() {
+        panic!("parameter \"screenshot\" must end with .pbm, .ppm or .png");
+    }
+    if let Some(record_video) = &record_video {
+        if VideoFormat::from_path(record_video).is_none() {
+            panic!("parameter \"record_video\" must end with .gif, .png or .y4m");
+        }
+    }
+
+    let rom_data = match fs::read(&Path::new(rom)) {
+        Ok(rom_data) => rom_data,
+        Err(error) => {
+            eprintln!("Could not read ROM {}: {}", rom, error);
+            std::process::exit(1);
+        }
+    };
+
+    // Controller profiles and per-ROM sections of the key config use the file name of the ROM
+    let rom_name = Path::new(rom).file_stem().unwrap().to_string_lossy();
+    keymap.add_controller_bindings(&rom_name);
+    if let Some(key_config) = key_config {
+        let result = fs::read_to_string(key_config)
+            .map_err(|error| error.to_string())
+            .and_then(|config| keymap.apply_config(&config, &rom_name));
+        if let Err(error) = result {
+            eprintln!("Could not load key config {}: {}", key_config, error);
+            std::process::exit(1);
+        }
+    }
+
+    // A movie replaces the configuration with the one it was recorded with
+    let config = match play {
+        Some(play) => {
+            let movie = File::open(play).and_then(|mut file| Movie::read(&mut file));
+            match movie {
+                Ok(movie) if movie.rom_checksum == crc32(&rom_data) => movie,
+                Ok(_) => panic!("The movie was recorded with a different ROM"),
+                Err(error) => panic!("The movie could not be loaded: {}", error),
+            }
+        }
+        None => Movie::new(
+            crc32(&rom_data),
+            seed,
+            clock_rate,
+            ignore_unknown_instructions,
+            program_address,
+            mode,
+            quirks,
+        ),
+    };
 
     // Initialize emulator
+    println!(
+        "Initializing processor with {} Hz ({})",
+        config.clock_rate,
+        config.mode.get_name()
+    );
     let mut emulator = Emulator::new(
-        clock_rate,
-        ignore_unknown_instructions,
-        program_address,
-        display_scale,
+        config.clock_rate,
+        config.ignore_unknown_instructions,
+        config.program_address,
+        config.mode,
+        config.quirks,
     );
+    emulator.cpu.set_seed(config.seed);
+    emulator.keypad.set_keymap(keymap);
+    emulator.keypad.set_min_hold(min_key_hold);
+    println!("Using random seed {}", config.seed);
+
+    match emulator.load_rom(&mut rom_data.as_slice()) {
+        Ok(rom_size) => println!(
+            "Loaded ROM ({} bytes) to memory at 0x{:X}",
+            rom_size, config.program_address
+        ),
+        Err(error) => {
+            eprintln!("Could not load ROM {}: {}", rom, error);
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(wav) = wav {
+        let output = File::create(wav).and_then(|file| WavWriter::new(BufWriter::new(file), tone));
+        match output {
+            Ok(output) => emulator.speaker.add_output(Box::new(output)),
+            Err(error) => {
+                eprintln!("Could not create WAV file {}: {}", wav, error);
+                std::process::exit(1);
+            }
+        }
+    }
 
-    let mut rom_file = match File::open(&Path::new(rom)) {
-        Ok(rom_file) => rom_file,
+    let mut movie = match (play, record) {
+        (Some(_), _) => Some(MovieSession::Playing(MoviePlayer::new(config))),
+        (None, Some(record)) => Some(MovieSession::Recording(
+            MovieRecorder::new(config),
+            PathBuf::from(record),
+        )),
+        (None, None) => None,
+    };
+
+    let mut rewind = RewindBuffer::new(rewind_memory << 20, rewind_interval);
+    let mut video = record_video.and_then(|path| start_video(&path, display_scale));
+
+    let mut success = true;
+    if headless {
+        // Without an explicit duration, the run ends with the screenshot or the movie
+        let frames = match (cycles, frames, &movie) {
+            (None, None, Some(MovieSession::Playing(player))) if screenshot_after.is_none() => {
+                Some(player.get_frames())
+            }
+            (None, None, _) => screenshot_after,
+            (_, frames, _) => frames,
+        };
+        success = run_headless(
+            &mut emulator,
+            &mut movie,
+            &mut debugger,
+            &mut rewind,
+            &mut video,
+            cycles,
+            frames,
+            screenshot_after.map(|frame| (frame, screenshot)),
+            display_scale,
+            debug_cpu,
+            debug_memory,
+        );
+    } else {
+        run_interactive(
+            &mut emulator,
+            &mut movie,
+            &mut debugger,
+            debugger_enabled,
+            &mut rewind,
+            &mut video,
+            rom,
+            Speed::new(speed),
+            display_scale,
+            key_mode,
+            if mute { None } else { Some(tone) },
+            debug_cpu,
+            debug_memory,
+        );
+    }
+
+    // Completes the WAV file
+    drop(emulator);
+    finish_video(video);
+
+    if (!finish_movie(movie) && headless) || !success {
+        std::process::exit(1);
+    }
+}
+
+fn run_disassembler(parameters: &ArgMatches) {
+    let rom = parameters.value_of("rom").unwrap();
+    let program_address = value_t!(parameters, "program_address", usize).unwrap();
+    let mode = Mode::from_name(parameters.value_of("mode").unwrap()).unwrap();
+
+    let rom_data = match fs::read(&Path::new(rom)) {
+        Ok(rom_data) => rom_data,
         Err(_) => panic!("The specified ROM file does not exist"),
     };
-    emulator.load_rom(&mut rom_file).unwrap();
-    // Initialize rodeo
+
+    println!(
+        "; {rom} ({size} bytes, {mode})",
+        rom = rom,
+        size = rom_data.len(),
+        mode = mode.get_name()
+    );
+    disassembler::disassemble(&mut io::stdout(), &rom_data, program_address, mode).unwrap();
+}
+
+fn run_assembler(parameters: &ArgMatches) {
+    let source = Path::new(parameters.value_of("source").unwrap());
+    let program_address = value_t!(parameters, "program_address", usize).unwrap();
+    let output = match parameters.value_of("output") {
+        Some(output) => PathBuf::from(output),
+        None => source.with_extension("ch8"),
+    };
+
+    let rom_data = match assembler::assemble_file(source, program_address) {
+        Ok(rom_data) => rom_data,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
+    if let Err(error) = fs::write(&output, &rom_data) {
+        panic!("Could not write ROM: {}", error);
+    }
+    println!("{} bytes written to {}", rom_data.len(), output.display());
+}
+
+fn run_interactive(
+    emulator: &mut Emulator,
+    movie: &mut Option<MovieSession>,
+    debugger: &mut Debugger,
+    debugger_enabled: bool, // faults pause in the debugger instead of stopping the emulation
+    rewind: &mut RewindBuffer,
+    video: &mut Option<VideoRecorder>,
+    rom: &str,
+    mut speed: Speed,
+    display_scale: u8,
+    key_mode: KeyMode,
+    tone: Option<ToneConfig>, // None if muted
+    debug_cpu: bool,
+    debug_memory: bool,
+) {
+    // Initialize audio, the output stays open for the whole run
     // This needs to be done before SDL2 initialization: https://github.com/RustAudio/rodio/issues/214
-    rodio::default_output_device();
+    if let Some(tone) = tone {
+        match AudioOutput::new(tone) {
+            Some(output) => emulator.speaker.add_output(Box::new(output)),
+            None => println!("No audio device found, sound is disabled"),
+        }
+    }
 
     // Initialize SDL2
     let sdl2_context = sdl2::init().unwrap();
@@ -67,53 +347,483 @@ fn main() {
     let mut sdl2_events = sdl2_context.event_pump().unwrap();
     let sdl2_timing = sdl2_context.timer().unwrap();
 
+    // Controllers are opened when SDL reports them, including those connected at startup
+    let sdl2_controllers = sdl2_context.game_controller().unwrap();
+    let mut controllers: Vec<GameController> = Vec::new();
+    let mut held_buttons: HashMap<u32, Vec<String>> = HashMap::new(); // host keys per controller instance
+
     let sdl2_video = sdl2_context.video().unwrap();
-    let window = emulator.display.create_window(&sdl2_video, rom);
-    let mut renderer = window.into_canvas().build().unwrap();
-    // Game loop
-    let mut last_step_time = get_time(&sdl2_timing);
+    let mut renderer = SdlRenderer::new(&sdl2_video, rom, display_scale);
+    renderer.set_status(&speed.get_label());
+    // Game loop, runs once per 60 Hz frame of emulated time
+    let frequency = sdl2_timing.performance_frequency();
+    let mut pacing_start = get_time(&sdl2_timing);
+    let mut paced_frames: u64 = 0;
+    let mut frame_rate = speed.get_frame_rate();
+    let mut state_slot = 0;
+    let mut rewinding = false;
+    let mut stopped = false;
 
     'running: loop {
-        let processing_start = get_time(&sdl2_timing);
-
-        // Events
+        // Events are handled at the start of a frame, so inputs never change within a frame
+        let playing = match movie {
+            Some(MovieSession::Playing(player)) => !player.is_finished(emulator.get_frame()),
+            _ => false,
+        };
         for event in sdl2_events.poll_iter() {
+            // Keys bound to the keypad are not used as hotkeys
+            let bound = match event {
+                Event::KeyDown {
+                    keycode, scancode, ..
+                }
+                | Event::KeyUp {
+                    keycode, scancode, ..
+                } => get_host_key(key_mode, keycode, scancode)
+                    .map_or(false, |host_key| emulator.keypad.is_bound(&host_key)),
+                _ => false,
+            };
             match event {
                 Event::Quit { .. } => break 'running,
                 Event::KeyDown {
-                    keycode: Some(keycode),
+                    keycode: Some(Keycode::F5),
+                    ..
+                } if !bound => save_state(emulator, rom, state_slot),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    ..
+                } if !bound => match movie {
+                    Some(_) => println!("Save states cannot be loaded while a movie is active"),
+                    None => load_state(emulator, rom, state_slot),
+                },
+                Event::KeyDown {
+                    keycode: Some(Keycode::F6),
+                    ..
+                } if !bound => {
+                    state_slot = (state_slot + STATE_SLOTS - 1) % STATE_SLOTS;
+                    println!("Selected save state slot {}", state_slot);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F7),
+                    ..
+                } if !bound => {
+                    state_slot = (state_slot + 1) % STATE_SLOTS;
+                    println!("Selected save state slot {}", state_slot);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F8),
+                    ..
+                } if !bound => debugger.pause(),
+                Event::KeyDown {
+                    keycode: Some(Keycode::Tab),
+                    repeat: false,
+                    ..
+                } if !bound => speed.set_turbo(true),
+                Event::KeyUp {
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } if !bound => speed.set_turbo(false),
+                Event::KeyDown {
+                    keycode: Some(Keycode::M),
+                    ..
+                } if !bound => speed.toggle_slow_motion(),
+                Event::KeyDown {
+                    keycode: Some(Keycode::P),
+                    ..
+                } if !bound => speed.toggle_pause(),
+                Event::KeyDown {
+                    keycode: Some(Keycode::Space),
+                    ..
+                } if !bound => speed.advance_frame(),
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backspace),
+                    repeat: false,
+                    ..
+                } if !bound => match movie {
+                    Some(_) => println!("Rewinding is not possible while a movie is active"),
+                    None => rewinding = true,
+                },
+                Event::KeyUp {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } if !bound => rewinding = false,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F10),
+                    ..
+                } if !bound => match video.take() {
+                    Some(recorder) => finish_video(Some(recorder)),
+                    None => {
+                        let path = get_free_path(Path::new(rom), "video", "gif");
+                        *video = start_video(&path, display_scale);
+                    }
+                },
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
                     ..
-                } => emulator.keypad.key_down(keycode),
+                } if !bound => save_screenshot(
+                    emulator,
+                    &get_screenshot_path(Path::new(rom)),
+                    display_scale,
+                ),
+                Event::KeyDown {
+                    timestamp,
+                    keycode,
+                    scancode,
+                    repeat: false,
+                    ..
+                } if !playing => {
+                    if let Some(host_key) = get_host_key(key_mode, keycode, scancode) {
+                        queue_input(emulator, host_key, true, timestamp);
+                    }
+                }
                 Event::KeyUp {
-                    keycode: Some(keycode),
+                    timestamp,
+                    keycode,
+                    scancode,
                     ..
-                } => emulator.keypad.key_up(keycode),
+                } if !playing => {
+                    if let Some(host_key) = get_host_key(key_mode, keycode, scancode) {
+                        queue_input(emulator, host_key, false, timestamp);
+                    }
+                }
+                Event::ControllerDeviceAdded { which, .. } => match sdl2_controllers.open(which) {
+                    Ok(controller) => {
+                        println!("Connected controller {}", controller.name());
+                        controllers.push(controller);
+                    }
+                    Err(error) => println!("Could not open controller: {}", error),
+                },
+                Event::ControllerDeviceRemoved {
+                    timestamp, which, ..
+                } => {
+                    controllers.retain(|controller| controller.instance_id() != which);
+                    for host_key in held_buttons.get(&which).cloned().unwrap_or_default() {
+                        release_button(emulator, &mut held_buttons, which, host_key, timestamp);
+                    }
+                    held_buttons.remove(&which);
+                    println!("Disconnected controller");
+                }
+                Event::ControllerButtonDown {
+                    timestamp,
+                    which,
+                    button,
+                } if !playing => {
+                    let host_key = format!("{}{}", CONTROLLER_PREFIX, button.string());
+                    let held = held_buttons.entry(which).or_default();
+                    if !held.contains(&host_key) {
+                        held.push(host_key.clone());
+                    }
+                    queue_input(emulator, host_key, true, timestamp);
+                }
+                Event::ControllerButtonUp {
+                    timestamp,
+                    which,
+                    button,
+                } if !playing => {
+                    let host_key = format!("{}{}", CONTROLLER_PREFIX, button.string());
+                    release_button(emulator, &mut held_buttons, which, host_key, timestamp);
+                }
                 _ => (),
             }
         }
 
-        // Emulation
-        let delta_time =
-            (get_time(&sdl2_timing) - last_step_time) * 1000 / sdl2_timing.performance_frequency();
-        emulator.step(
-            delta_time as f32,
-            &mut renderer,
-            sound,
-            debug_cpu,
-            debug_memory,
-        );
+        // Speed changes restart the frame pacing
+        if speed.get_frame_rate() != frame_rate {
+            frame_rate = speed.get_frame_rate();
+            pacing_start = get_time(&sdl2_timing);
+            paced_frames = 0;
+            renderer.set_status(&speed.get_label());
+        }
 
-        let frame_wait_duration = 1.0 / emulator.cpu.get_clock_rate() * 1000.0;
-        let processing_time = (get_time(&sdl2_timing) - processing_start) * 1000
-            / sdl2_timing.performance_frequency();
-        let sleep_time = if frame_wait_duration as u32 > processing_time as u32 {
-            frame_wait_duration as u32 - processing_time as u32
-        } else {
-            0
-        };
+        // Emulation, while rewinding the frames are restored backwards instead.
+        // The speed is asked every frame, so its frame counting is not affected by rewinding.
+        let should_run_frame = speed.should_run_frame();
+        let rewound = rewinding && rewind.rewind(emulator);
+        let run_frame = !stopped && !rewound && should_run_frame;
+        if run_frame {
+            // The queued input is applied first, so movies record the keys of this frame
+            emulator.keypad.update(emulator.get_frame());
+            update_movie(movie, emulator);
+            rewind.record(emulator);
+
+            loop {
+                // Debugging, the window shows the current state but is not updated while the prompt is shown
+                if debugger.should_break(emulator, &mut io::stdout()) {
+                    emulator.render(&mut renderer);
+                    if run_debugger(debugger, emulator, rewind, movie) == DebuggerAction::Quit {
+                        break 'running;
+                    }
+                    pacing_start = get_time(&sdl2_timing);
+                    paced_frames = 0;
+                }
+
+                // Faults stop the emulation in the debugger, where the state can be inspected or fixed.
+                // Without the debugger, the window stays open with the fault in the title.
+                if let Err(error) = emulator.step(debug_cpu, debug_memory) {
+                    eprintln!("Emulation stopped: {}", error);
+                    if debugger_enabled {
+                        debugger.pause();
+                        continue;
+                    }
+                    renderer.set_status(&format!("Stopped: {}", error));
+                    stopped = true;
+                    break;
+                }
+                debugger.check_watchpoints(emulator, &mut io::stdout());
+                if emulator.cpu.has_exited() {
+                    println!("Program exited");
+                    break 'running;
+                }
+                if emulator.get_frame_start().is_some() {
+                    record_video(video, emulator);
+                    break;
+                }
+            }
+        }
+        emulator.render(&mut renderer);
+
+        // Frame pacing, the target times are computed from the start to avoid drift
+        paced_frames += 1;
+        let target_time =
+            pacing_start + (paced_frames as f64 * frequency as f64 / frame_rate as f64) as u64;
+        let time = get_time(&sdl2_timing);
+        if time < target_time {
+            thread::sleep(Duration::from_nanos(
+                (target_time - time) * 1_000_000_000 / frequency,
+            ));
+        } else if time - target_time > frequency / 10 {
+            // Too slow or stalled, skip the missed frames instead of catching up
+            pacing_start = time;
+            paced_frames = 0;
+        }
+    }
+}
+
+// Runs without video and audio devices, using emulated instead of wall clock time.
+// Returns false if the emulation stopped with a fault.
+fn run_headless(
+    emulator: &mut Emulator,
+    movie: &mut Option<MovieSession>,
+    debugger: &mut Debugger,
+    rewind: &mut RewindBuffer,
+    video: &mut Option<VideoRecorder>,
+    cycles: Option<u64>,
+    frames: Option<u64>,
+    screenshot: Option<(u64, PathBuf)>,
+    display_scale: u8,
+    debug_cpu: bool,
+    debug_memory: bool,
+) -> bool {
+    let mut executed_cycles = 0;
+    let mut result = Ok(());
+    loop {
+        let frame = emulator.get_frame_start();
+        if let (Some(frame), Some((screenshot_frame, path))) = (frame, &screenshot) {
+            if frame == *screenshot_frame {
+                save_screenshot(emulator, path, display_scale);
+            }
+        }
+        if cycles.map_or(false, |cycles| executed_cycles >= cycles)
+            || frame.map_or(false, |frame| {
+                frames.map_or(false, |frames| frame >= frames)
+            })
+        {
+            break;
+        }
+
+        if debugger.should_break(emulator, &mut io::stdout())
+            && run_debugger(debugger, emulator, rewind, movie) == DebuggerAction::Quit
+        {
+            break;
+        }
+
+        update_movie(movie, emulator);
+        rewind.record(emulator);
+        result = emulator.step(debug_cpu, debug_memory);
+        if result.is_err() {
+            break;
+        }
+        debugger.check_watchpoints(emulator, &mut io::stdout());
+        executed_cycles += 1;
+        if emulator.get_frame_start().is_some() {
+            record_video(video, emulator);
+        }
+
+        if emulator.cpu.has_exited() {
+            println!("Program exited");
+            break;
+        }
+    }
+
+    println!(
+        "Executed {cycles} cycles ({frames} frames)",
+        cycles = executed_cycles,
+        frames = emulator.get_frame()
+    );
+    if let Err(error) = &result {
+        println!("Emulation stopped: {}", error);
+    }
+    result.is_ok()
+}
+
+fn save_state(emulator: &Emulator, rom: &str, slot: u8) {
+    let path = get_slot_path(Path::new(rom), slot);
+    let result = File::create(&path).and_then(|mut file| emulator.save_state(&mut file));
+
+    match result {
+        Ok(()) => println!("Saved state to slot {} ({})", slot, path.display()),
+        Err(error) => println!("Could not save state to slot {}: {}", slot, error),
+    }
+}
+
+fn load_state(emulator: &mut Emulator, rom: &str, slot: u8) {
+    let path = get_slot_path(Path::new(rom), slot);
+    let result = File::open(&path).and_then(|mut file| emulator.load_state(&mut file));
+
+    match result {
+        Ok(()) => println!("Loaded state from slot {} ({})", slot, path.display()),
+        Err(error) => println!("Could not load state from slot {}: {}", slot, error),
+    }
+}
+
+// Stepping back re-executes recorded inputs, which would desync a movie
+fn run_debugger(
+    debugger: &mut Debugger,
+    emulator: &mut Emulator,
+    rewind: &mut RewindBuffer,
+    movie: &Option<MovieSession>,
+) -> DebuggerAction {
+    let rewind = match movie {
+        Some(_) => None,
+        None => Some(rewind),
+    };
+    let stdin = io::stdin();
+    debugger.prompt(emulator, rewind, &mut stdin.lock(), &mut io::stdout())
+}
+
+// Movie that is recorded or played back during the run
+enum MovieSession {
+    Recording(MovieRecorder, PathBuf),
+    Playing(MoviePlayer),
+}
+
+fn update_movie(movie: &mut Option<MovieSession>, emulator: &mut Emulator) {
+    let frame = match emulator.get_frame_start() {
+        Some(frame) => frame,
+        None => return,
+    };
+
+    match movie {
+        Some(MovieSession::Recording(recorder, _)) => recorder.record_frame(frame, emulator),
+        Some(MovieSession::Playing(player)) => {
+            player.play_frame(frame, emulator);
+            if frame == player.get_frames() {
+                println!("Movie finished after {} frames", frame);
+            }
+        }
+        None => (),
+    }
+}
+
+// Saves a recorded movie, returns false if the movie could not be saved or played back without desyncs
+fn finish_movie(movie: Option<MovieSession>) -> bool {
+    match movie {
+        Some(MovieSession::Recording(recorder, path)) => {
+            let result =
+                File::create(&path).and_then(|mut file| recorder.finish().write(&mut file));
+            match &result {
+                Ok(()) => println!("Saved movie to {}", path.display()),
+                Err(error) => println!("Could not save movie: {}", error),
+            }
+            result.is_ok()
+        }
+        Some(MovieSession::Playing(player)) => {
+            println!("Movie played back with {} desyncs", player.get_desyncs());
+            player.get_desyncs() == 0
+        }
+        None => true,
+    }
+}
+
+fn queue_input(emulator: &mut Emulator, host_key: String, pressed: bool, time: u32) {
+    emulator.keypad.queue_event(InputEvent {
+        host_key: host_key,
+        pressed: pressed,
+        time: time,
+    });
+}
+
+// Controllers share the button names, a button stays pressed while another controller holds it
+fn release_button(
+    emulator: &mut Emulator,
+    held_buttons: &mut HashMap<u32, Vec<String>>,
+    which: u32,
+    host_key: String,
+    time: u32,
+) {
+    if let Some(held) = held_buttons.get_mut(&which) {
+        held.retain(|held| *held != host_key);
+    }
+    if !held_buttons.values().any(|held| held.contains(&host_key)) {
+        queue_input(emulator, host_key, false, time);
+    }
+}
+
+// Name of a key in the keymap
+fn get_host_key(
+    key_mode: KeyMode,
+    keycode: Option<Keycode>,
+    scancode: Option<Scancode>,
+) -> Option<String> {
+    match key_mode {
+        KeyMode::Keycode => keycode.map(|keycode| keycode.name()),
+        KeyMode::Scancode => scancode.map(|scancode| scancode.name().to_string()),
+    }
+}
+
+fn start_video(path: &Path, display_scale: u8) -> Option<VideoRecorder> {
+    match VideoRecorder::new(path, display_scale as usize) {
+        Ok(video) => {
+            println!("Recording video to {}", path.display());
+            Some(video)
+        }
+        Err(error) => {
+            println!("Could not record video: {}", error);
+            None
+        }
+    }
+}
+
+// Adds the display after a completed frame, recording stops on errors
+fn record_video(video: &mut Option<VideoRecorder>, emulator: &Emulator) {
+    if let Some(recorder) = video {
+        if let Err(error) = recorder.add_frame(&emulator.display) {
+            println!("Could not record video: {}", error);
+            *video = None;
+        }
+    }
+}
+
+fn finish_video(video: Option<VideoRecorder>) {
+    if let Some(video) = video {
+        let frames = video.get_frame_count();
+        match video.finish() {
+            Ok(()) => println!("Saved video ({} frames)", frames),
+            Err(error) => println!("Could not save video: {}", error),
+        }
+    }
+}
+
+fn save_screenshot(emulator: &Emulator, path: &Path, display_scale: u8) {
+    let format = ImageFormat::from_path(path).unwrap();
+    let result = File::create(path).and_then(|mut file| {
+        emulator
+            .display
+            .save_screenshot(&mut file, format, display_scale as usize)
+    });
 
-        last_step_time = get_time(&sdl2_timing);
-        thread::sleep(Duration::new(0, sleep_time * 1000000));
+    match result {
+        Ok(()) => println!("Saved screenshot to {}", path.display()),
+        Err(error) => println!("Could not save screenshot: {}", error),
     }
 }
 
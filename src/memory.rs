@@ -1,43 +1,79 @@
-const MEMORY_SIZE: usize = 4096;
+use std::cell::RefCell;
+use std::io::Error;
 
+use crate::savestate::{invalid_data, StateReader, StateWriter};
+
+/// Memory of Chip-8 and SUPER-CHIP in bytes
+pub const MEMORY_SIZE: usize = 4096;
+/// Memory of XO-CHIP in bytes
+pub const XO_CHIP_MEMORY_SIZE: usize = 65536;
+
+/// Kind of a tracked memory access
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MemoryAccess {
+    Read,
+    Write,
+}
+
+/// Byte-addressed main memory, indices must be smaller than get_size
 pub trait MemoryTrait {
     fn read(&self, index: usize) -> u8;
-    fn read_all(&mut self) -> &mut [u8; MEMORY_SIZE];
+    /// Direct access to all cells, used to copy the ROM
+    fn read_all(&mut self) -> &mut [u8];
     fn write(&mut self, index: usize, value: u8);
+    /// Sets all cells to zero
     fn clear(&mut self);
     fn get_size(&self) -> usize;
+    /// Prints a hex dump to stdout
     fn print_debug_info(&self);
 }
 
+/// Main memory of the emulator
 pub struct Memory {
-    cells: [u8; MEMORY_SIZE], // 8-bit memory. Public in order to allow batch access.
+    cells: Vec<u8>, // 8-bit memory. Public in order to allow batch access.
+
+    // Access tracking (debugger watchpoints)
+    tracking: bool,
+    accesses: RefCell<Vec<(usize, MemoryAccess)>>,
 }
 
 impl MemoryTrait for Memory {
     fn read(&self, index: usize) -> u8 {
+        if self.tracking {
+            self.accesses.borrow_mut().push((index, MemoryAccess::Read));
+        }
+
         self.cells[index]
     }
 
-    fn read_all(&mut self) -> &mut [u8; MEMORY_SIZE] {
+    fn read_all(&mut self) -> &mut [u8] {
         &mut self.cells
     }
 
     fn write(&mut self, index: usize, value: u8) {
+        if self.tracking {
+            self.accesses
+                .borrow_mut()
+                .push((index, MemoryAccess::Write));
+        }
+
         self.cells[index] = value;
     }
 
     fn clear(&mut self) {
-        self.cells = [0; MEMORY_SIZE];
+        for cell in self.cells.iter_mut() {
+            *cell = 0;
+        }
     }
 
     fn get_size(&self) -> usize {
-        MEMORY_SIZE
+        self.cells.len()
     }
 
     fn print_debug_info(&self) {
         println!("");
 
-        for index in 0..MEMORY_SIZE {
+        for index in 0..self.get_size() {
             if index == 0 || (index > 1 && Memory::modulo(index, 16) == 0) {
                 print!("0x{:03X} ", index);
             }
@@ -52,17 +88,45 @@ impl MemoryTrait for Memory {
 }
 
 impl Memory {
-    pub fn new() -> Memory {
-        println!(
-            "Initializing {memory_size} bytes of main memory",
-            memory_size = MEMORY_SIZE
-        );
-
+    /// Creates a memory of memory_size bytes, initialized with zeros
+    pub fn new(memory_size: usize) -> Memory {
         Memory {
-            cells: [0; MEMORY_SIZE],
+            cells: vec![0; memory_size],
+
+            // Access tracking
+            tracking: false,
+            accesses: RefCell::new(Vec::new()),
         }
     }
 
+    /// Records reads and writes until tracking is disabled again. Accesses through read_all are not tracked.
+    pub fn set_tracking(&mut self, tracking: bool) {
+        self.tracking = tracking;
+        self.accesses.borrow_mut().clear();
+    }
+
+    /// Returns and forgets the accesses since the last call
+    pub fn take_accesses(&mut self) -> Vec<(usize, MemoryAccess)> {
+        self.accesses.replace(Vec::new())
+    }
+
+    /// Writes all cells to a save state
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u32(self.cells.len() as u32);
+        state.write_bytes(&self.cells);
+    }
+
+    /// Reads the cells written by save_state, the memory size must match
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), Error> {
+        if state.read_u32()? as usize != self.cells.len() {
+            return Err(invalid_data("Save state has a different memory size"));
+        }
+        let size = self.cells.len();
+        self.cells.copy_from_slice(state.read_bytes(size)?);
+
+        Ok(())
+    }
+
     fn modulo(n1: usize, n2: usize) -> usize {
         n1 - n2 * ((n1 / n2) as usize)
     }
@@ -0,0 +1,315 @@
+use std::io::{BufRead, BufReader, Error, Read, Write};
+
+use crate::cpu::*;
+use crate::emulator::Emulator;
+use crate::keypad::{get_keys, set_keys};
+use crate::quirks::Quirks;
+use crate::savestate::invalid_data;
+
+const MOVIE_HEADER: &str = "CHIP-8 MOVIE 1";
+const CHECKSUM_INTERVAL: u64 = 60; // Frames between two state checksums
+
+// Input recording that reproduces a run together with the ROM and the configuration.
+// Movies are text files with one entry per line, keys are stored as bit mask (bit n = key n):
+//   rom <crc32>, seed <seed>, clock-rate <hz>, ignore-unknown-instructions <bool>,
+//   program-address <address>, mode <mode>, quirks <names...>, frames <count>,
+//   input <frame> <keys>, checksum <frame> <crc32>
+#[derive(Debug, PartialEq)]
+pub struct Movie {
+    // Configuration
+    pub rom_checksum: u32,
+    pub seed: u64,
+    pub clock_rate: f32,
+    pub ignore_unknown_instructions: bool,
+    pub program_address: usize,
+    pub mode: Mode,
+    pub quirks: Quirks,
+
+    // Recording
+    pub frames: u64,
+    inputs: Vec<(u64, u16)>,    // frame, pressed keys
+    checksums: Vec<(u64, u32)>, // frame, checksum of the emulator state at the start of the frame
+}
+
+impl Movie {
+    pub fn new(
+        rom_checksum: u32,
+        seed: u64,
+        clock_rate: f32,
+        ignore_unknown_instructions: bool,
+        program_address: usize,
+        mode: Mode,
+        quirks: Quirks,
+    ) -> Movie {
+        Movie {
+            // Configuration
+            rom_checksum: rom_checksum,
+            seed: seed,
+            clock_rate: clock_rate,
+            ignore_unknown_instructions: ignore_unknown_instructions,
+            program_address: program_address,
+            mode: mode,
+            quirks: quirks,
+
+            // Recording
+            frames: 0,
+            inputs: Vec::new(),
+            checksums: Vec::new(),
+        }
+    }
+
+    pub fn write(&self, writer: &mut dyn Write) -> Result<(), Error> {
+        writeln!(writer, "{}", MOVIE_HEADER)?;
+        writeln!(writer, "rom {:08x}", self.rom_checksum)?;
+        writeln!(writer, "seed {}", self.seed)?;
+        writeln!(writer, "clock-rate {}", self.clock_rate)?;
+        writeln!(
+            writer,
+            "ignore-unknown-instructions {}",
+            self.ignore_unknown_instructions
+        )?;
+        writeln!(writer, "program-address {}", self.program_address)?;
+        writeln!(writer, "mode {}", self.mode.get_name())?;
+        writeln!(writer, "quirks {}", self.quirks.get_names().join(" "))?;
+        writeln!(writer, "frames {}", self.frames)?;
+
+        for (frame, keys) in self.inputs.iter() {
+            writeln!(writer, "input {} {:04x}", frame, keys)?;
+        }
+        for (frame, checksum) in self.checksums.iter() {
+            writeln!(writer, "checksum {} {:08x}", frame, checksum)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read(reader: &mut dyn Read) -> Result<Movie, Error> {
+        let mut lines = BufReader::new(reader).lines();
+        if lines.next().transpose()?.as_ref().map(|line| line.trim()) != Some(MOVIE_HEADER) {
+            return Err(invalid_data("Not a movie file"));
+        }
+
+        let mut movie = Movie::new(0, 0, 0.0, false, 0, Mode::Chip8, Quirks::default());
+        for line in lines {
+            let line = line?;
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            let invalid = || invalid_data(&format!("Invalid movie entry \"{}\"", line));
+
+            match tokens.as_slice() {
+                [] => (),
+                ["rom", checksum] => {
+                    movie.rom_checksum = parse_hex(checksum).ok_or_else(invalid)?
+                }
+                ["seed", seed] => movie.seed = seed.parse().map_err(|_| invalid())?,
+                ["clock-rate", rate] => movie.clock_rate = rate.parse().map_err(|_| invalid())?,
+                ["ignore-unknown-instructions", ignore] => {
+                    movie.ignore_unknown_instructions = ignore.parse().map_err(|_| invalid())?
+                }
+                ["program-address", address] => {
+                    movie.program_address = address.parse().map_err(|_| invalid())?
+                }
+                ["mode", mode] => movie.mode = Mode::from_name(mode).ok_or_else(invalid)?,
+                ["quirks", names @ ..] => {
+                    movie.quirks = Quirks::from_names(names).ok_or_else(invalid)?
+                }
+                ["frames", frames] => movie.frames = frames.parse().map_err(|_| invalid())?,
+                ["input", frame, keys] => movie.inputs.push((
+                    frame.parse().map_err(|_| invalid())?,
+                    parse_hex(keys).ok_or_else(invalid)? as u16,
+                )),
+                ["checksum", frame, checksum] => movie.checksums.push((
+                    frame.parse().map_err(|_| invalid())?,
+                    parse_hex(checksum).ok_or_else(invalid)?,
+                )),
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(movie)
+    }
+}
+
+// Records the keypad at the start of every frame
+pub struct MovieRecorder {
+    movie: Movie,
+    keys: Option<u16>,
+}
+
+impl MovieRecorder {
+    pub fn new(movie: Movie) -> MovieRecorder {
+        MovieRecorder {
+            movie: movie,
+            keys: None,
+        }
+    }
+
+    pub fn record_frame(&mut self, frame: u64, emulator: &mut Emulator) {
+        let keys = get_keys(&mut emulator.keypad);
+        if self.keys != Some(keys) {
+            self.movie.inputs.push((frame, keys));
+            self.keys = Some(keys);
+        }
+        if frame % CHECKSUM_INTERVAL == 0 {
+            self.movie.checksums.push((frame, emulator.get_checksum()));
+        }
+
+        self.movie.frames = frame + 1;
+    }
+
+    pub fn finish(self) -> Movie {
+        self.movie
+    }
+}
+
+// Feeds the recorded inputs to the keypad and compares the state checksums
+pub struct MoviePlayer {
+    movie: Movie,
+    input_index: usize,
+    checksum_index: usize,
+    desyncs: u64,
+}
+
+impl MoviePlayer {
+    pub fn new(movie: Movie) -> MoviePlayer {
+        MoviePlayer {
+            movie: movie,
+            input_index: 0,
+            checksum_index: 0,
+            desyncs: 0,
+        }
+    }
+
+    pub fn play_frame(&mut self, frame: u64, emulator: &mut Emulator) {
+        while let Some((_, keys)) = self
+            .movie
+            .inputs
+            .get(self.input_index)
+            .filter(|input| input.0 <= frame)
+        {
+            set_keys(&mut emulator.keypad, *keys);
+            self.input_index += 1;
+        }
+
+        while let Some((checksum_frame, checksum)) = self
+            .movie
+            .checksums
+            .get(self.checksum_index)
+            .filter(|checksum| checksum.0 <= frame)
+        {
+            let actual = emulator.get_checksum();
+            if *checksum_frame == frame && *checksum != actual {
+                println!(
+                    "Movie desynced at frame {} (checksum {:08x}, expected {:08x})",
+                    frame, actual, checksum
+                );
+                self.desyncs += 1;
+            }
+            self.checksum_index += 1;
+        }
+    }
+
+    pub fn is_finished(&self, frame: u64) -> bool {
+        frame >= self.movie.frames
+    }
+
+    pub fn get_frames(&self) -> u64 {
+        self.movie.frames
+    }
+
+    pub fn get_desyncs(&self) -> u64 {
+        self.desyncs
+    }
+}
+
+fn parse_hex(value: &str) -> Option<u32> {
+    u32::from_str_radix(value, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keypad::KeypadTrait;
+    use std::io::Cursor;
+
+    fn instantiate_emulator() -> Emulator {
+        let mut emulator = Emulator::new(600.0, false, 0x200, Mode::Chip8, Quirks::default());
+        emulator.cpu.set_seed(42);
+
+        // Draws a random sprite from the font whenever a key is pressed
+        let program = vec![0xF0, 0x0A, 0xC1, 0x0F, 0xF1, 0x29, 0xD2, 0x25, 0x12, 0x00];
+        emulator.load_rom(&mut Cursor::new(program)).unwrap();
+        emulator
+    }
+
+    fn run(emulator: &mut Emulator, frames: u64, mut update: impl FnMut(u64, &mut Emulator)) {
+        for frame in 0..frames {
+            update(frame, emulator);
+            emulator.run_frame(false, false).unwrap();
+        }
+    }
+
+    fn record() -> Movie {
+        let mut emulator = instantiate_emulator();
+        let mut recorder = MovieRecorder::new(Movie::new(
+            0x1234,
+            42,
+            600.0,
+            false,
+            0x200,
+            Mode::Chip8,
+            Quirks::default(),
+        ));
+
+        run(&mut emulator, 200, |frame, emulator| {
+            emulator.keypad.set_key(0x5, frame % 50 < 10);
+            recorder.record_frame(frame, emulator);
+        });
+        recorder.finish()
+    }
+
+    #[test]
+    fn test_write_and_read_movie() {
+        let movie = record();
+        let mut file = Vec::new();
+        movie.write(&mut file).unwrap();
+
+        let restored = Movie::read(&mut Cursor::new(file)).unwrap();
+
+        assert_eq!(restored, movie);
+        assert_eq!(restored.frames, 200);
+        assert_eq!(restored.inputs[0..2], [(0, 0x0020), (10, 0x0000)]);
+    }
+
+    #[test]
+    fn test_read_rejects_other_files() {
+        let result = Movie::read(&mut Cursor::new(b"C8ST".to_vec()));
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_playback_reproduces_recording() {
+        let mut player = MoviePlayer::new(record());
+        let mut emulator = instantiate_emulator();
+
+        run(&mut emulator, 200, |frame, emulator| {
+            player.play_frame(frame, emulator)
+        });
+
+        assert_eq!(player.get_desyncs(), 0);
+        assert!(player.is_finished(200));
+    }
+
+    #[test]
+    fn test_playback_detects_desync() {
+        let mut player = MoviePlayer::new(record());
+        let mut emulator = instantiate_emulator();
+        emulator.cpu.set_seed(43);
+
+        run(&mut emulator, 200, |frame, emulator| {
+            player.play_frame(frame, emulator)
+        });
+
+        assert!(player.get_desyncs() > 0);
+    }
+}
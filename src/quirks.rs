@@ -0,0 +1,122 @@
+// Behaviour of instructions whose interpretation differs between CHIP-8 implementations
+// See https://github.com/Timendus/chip8-test-suite#quirks-test for an overview
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quirks {
+    pub vf_reset: bool,         // 8xy1, 8xy2 and 8xy3 reset VF to 0
+    pub memory_increment: bool, // Fx55 and Fx65 increment I by x + 1
+    pub display_wait: bool,     // Dxyn waits for the vertical blank interrupt (60 Hz)
+    pub clipping: bool,         // Sprites are clipped at the display edges instead of wrapping
+    pub shifting: bool,         // 8xy6 and 8xyE shift Vx instead of Vy
+    pub jumping: bool,          // Bnnn jumps to xnn + Vx instead of nnn + V0
+    pub key_release: bool,      // Fx0A waits until the pressed key is released
+}
+
+impl Default for Quirks {
+    // Behaviour of this emulator before quirks were configurable
+    fn default() -> Quirks {
+        Quirks {
+            vf_reset: false,
+            memory_increment: true,
+            display_wait: false,
+            clipping: false,
+            shifting: true,
+            jumping: false,
+            key_release: false,
+        }
+    }
+}
+
+// Names as used in movie files, in the order of the fields
+const QUIRK_NAMES: [&str; 7] = [
+    "vf-reset",
+    "memory-increment",
+    "display-wait",
+    "clipping",
+    "shifting",
+    "jumping",
+    "key-release",
+];
+
+impl Quirks {
+    // Original interpreter on the RCA COSMAC VIP
+    pub const COSMAC_VIP: Quirks = Quirks {
+        vf_reset: true,
+        memory_increment: true,
+        display_wait: true,
+        clipping: true,
+        shifting: false,
+        jumping: false,
+        key_release: true,
+    };
+
+    // CHIP-48 interpreter on the HP-48 calculators
+    pub const CHIP_48: Quirks = Quirks {
+        vf_reset: false,
+        memory_increment: true,
+        display_wait: false,
+        clipping: true,
+        shifting: true,
+        jumping: true,
+        key_release: false,
+    };
+
+    // SUPER-CHIP 1.1 interpreter on the HP-48 calculators
+    pub const SCHIP: Quirks = Quirks {
+        vf_reset: false,
+        memory_increment: false,
+        display_wait: false,
+        clipping: true,
+        shifting: true,
+        jumping: true,
+        key_release: false,
+    };
+
+    // XO-CHIP as implemented by Octo
+    pub const XO_CHIP: Quirks = Quirks {
+        vf_reset: false,
+        memory_increment: true,
+        display_wait: false,
+        clipping: false,
+        shifting: false,
+        jumping: false,
+        key_release: false,
+    };
+
+    // Returns the names of the enabled quirks
+    pub fn get_names(&self) -> Vec<&'static str> {
+        let flags = [
+            self.vf_reset,
+            self.memory_increment,
+            self.display_wait,
+            self.clipping,
+            self.shifting,
+            self.jumping,
+            self.key_release,
+        ];
+
+        QUIRK_NAMES
+            .iter()
+            .zip(flags.iter())
+            .filter(|(_, enabled)| **enabled)
+            .map(|(name, _)| *name)
+            .collect()
+    }
+
+    // Enables exactly the named quirks, returns None for unknown names
+    pub fn from_names(names: &[&str]) -> Option<Quirks> {
+        let mut flags = [false; 7];
+        for name in names {
+            flags[QUIRK_NAMES.iter().position(|quirk| quirk == name)?] = true;
+        }
+
+        Some(Quirks {
+            vf_reset: flags[0],
+            memory_increment: flags[1],
+            display_wait: flags[2],
+            clipping: flags[3],
+            shifting: flags[4],
+            jumping: flags[5],
+            key_release: flags[6],
+        })
+    }
+}
@@ -0,0 +1,61 @@
+use std::io::Error;
+
+use crate::savestate::{StateReader, StateWriter};
+
+#[cfg(test)]
+use mockall::{automock, predicate::*};
+
+// Source of random numbers for Cxkk
+#[cfg_attr(test, automock)]
+pub trait RandomTrait {
+    fn next_u8(&mut self) -> u8;
+    fn save_state(&self, state: &mut StateWriter);
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), Error>;
+}
+
+// Pseudo random number generator (SplitMix64).
+// Unlike the system generator it can be seeded, which makes runs reproducible.
+pub struct Random {
+    seed: u64,
+    state: u64,
+}
+
+impl RandomTrait for Random {
+    fn next_u8(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u64(self.seed);
+        state.write_u64(self.state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), Error> {
+        self.seed = state.read_u64()?;
+        self.state = state.read_u64()?;
+
+        Ok(())
+    }
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        Random {
+            seed: seed,
+            state: seed,
+        }
+    }
+
+    pub fn from_entropy() -> Random {
+        Random::new(rand::random::<u64>())
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+
+        let mut value = self.state;
+        value = (value ^ (value >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        value = (value ^ (value >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        value ^ (value >> 31)
+    }
+}
@@ -0,0 +1,104 @@
+use crate::display::*;
+
+// Frontend that presents the contents of the display
+pub trait RendererTrait {
+    fn render(&mut self, display: &Display);
+}
+
+#[cfg(feature = "sdl2")]
+pub use self::sdl::SdlRenderer;
+
+#[cfg(feature = "sdl2")]
+mod sdl {
+    extern crate sdl2;
+
+    use sdl2::pixels::Color;
+    use sdl2::rect::Rect;
+    use sdl2::render::Canvas;
+    use sdl2::video::Window;
+
+    use super::RendererTrait;
+    use crate::display::*;
+
+    pub struct SdlRenderer {
+        canvas: Canvas<Window>,
+
+        // Configuration
+        display_scale: u8,
+        title_addition: String,
+    }
+
+    impl RendererTrait for SdlRenderer {
+        fn render(&mut self, display: &Display) {
+            // The window size is fixed, so the size of a pixel depends on the current resolution
+            let (width, height) = (display.get_width(), display.get_height());
+            let window_width = DISPLAY_WIDTH * self.display_scale as usize;
+            let window_height = DISPLAY_HEIGHT * self.display_scale as usize;
+
+            for x in 0..width {
+                for y in 0..height {
+                    let (red, green, blue) = PALETTE[display.get_pixel(x, y) as usize];
+                    self.canvas.set_draw_color(Color::RGB(red, green, blue));
+
+                    let left = x * window_width / width;
+                    let top = y * window_height / height;
+                    let right = (x + 1) * window_width / width;
+                    let bottom = (y + 1) * window_height / height;
+                    self.canvas
+                        .fill_rect(Rect::new(
+                            left as i32,
+                            top as i32,
+                            (right - left) as u32,
+                            (bottom - top) as u32,
+                        ))
+                        .unwrap();
+                }
+            }
+
+            self.canvas.present();
+        }
+    }
+
+    impl SdlRenderer {
+        pub fn new(
+            sdl_video: &sdl2::VideoSubsystem,
+            title_addition: &str,
+            display_scale: u8,
+        ) -> SdlRenderer {
+            println!("Initializing renderer");
+
+            let title = SdlRenderer::get_title(title_addition, None);
+            let window = sdl_video
+                .window(
+                    &title,
+                    DISPLAY_WIDTH as u32 * display_scale as u32,
+                    DISPLAY_HEIGHT as u32 * display_scale as u32,
+                )
+                .position_centered()
+                .opengl()
+                .build()
+                .unwrap();
+
+            SdlRenderer {
+                canvas: window.into_canvas().build().unwrap(),
+
+                // Configuration
+                display_scale: display_scale,
+                title_addition: title_addition.to_string(),
+            }
+        }
+
+        // Shows a status like the emulation speed in the window title
+        pub fn set_status(&mut self, status: &str) {
+            let title = SdlRenderer::get_title(&self.title_addition, Some(status));
+            self.canvas.window_mut().set_title(&title).unwrap();
+        }
+
+        fn get_title(title_addition: &str, status: Option<&str>) -> String {
+            match status {
+                Some(status) => format!("Chip-8 Emulator ({}) [{}]", title_addition, status),
+                None => format!("Chip-8 Emulator ({})", title_addition),
+            }
+        }
+    }
+}
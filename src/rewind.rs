@@ -0,0 +1,264 @@
+use std::collections::VecDeque;
+
+use crate::emulator::Emulator;
+use crate::keypad::{get_keys, set_keys};
+
+// Snapshot of the emulator at the start of a frame
+struct Snapshot {
+    frame: u64,
+    iteration: u64,
+    state: Vec<u8>, // save state, compressed
+}
+
+// Ring buffer of emulator snapshots for rewinding and stepping back in the debugger.
+// Snapshots are taken every few frames and dropped oldest first when the memory budget is exceeded.
+// The inputs of every frame are kept as well, so instructions between snapshots can be re-executed.
+pub struct RewindBuffer {
+    snapshots: VecDeque<Snapshot>,
+    inputs: VecDeque<(u64, u16)>, // frame, pressed keys
+    size: usize,                  // bytes used by the snapshots
+
+    // Configuration
+    budget: usize, // bytes, 0 disables the buffer
+    interval: u64, // frames between snapshots
+}
+
+impl RewindBuffer {
+    pub fn new(budget: usize, interval: u64) -> RewindBuffer {
+        RewindBuffer {
+            snapshots: VecDeque::new(),
+            inputs: VecDeque::new(),
+            size: 0,
+
+            // Configuration
+            budget: budget,
+            interval: interval.max(1),
+        }
+    }
+
+    // Called at the start of every frame, after the inputs of the frame have been applied
+    pub fn record(&mut self, emulator: &mut Emulator) {
+        let frame = match emulator.get_frame_start() {
+            Some(frame) if self.budget > 0 => frame,
+            _ => return,
+        };
+
+        // Continuing from an earlier frame replaces the recorded future
+        self.truncate(frame);
+        self.inputs
+            .push_back((frame, get_keys(&mut emulator.keypad)));
+
+        if frame % self.interval == 0 {
+            let mut state = Vec::new();
+            emulator.save_state(&mut state).unwrap();
+            let state = compress(&state);
+
+            self.size += state.len();
+            self.snapshots.push_back(Snapshot {
+                frame: frame,
+                iteration: emulator.get_iteration(),
+                state: state,
+            });
+        }
+
+        while self.size > self.budget && self.snapshots.len() > 1 {
+            let snapshot = self.snapshots.pop_front().unwrap();
+            self.size -= snapshot.state.len();
+        }
+        let oldest_frame = self
+            .snapshots
+            .front()
+            .map_or(frame, |snapshot| snapshot.frame);
+        while self
+            .inputs
+            .front()
+            .map_or(false, |input| input.0 < oldest_frame)
+        {
+            self.inputs.pop_front();
+        }
+    }
+
+    // Restores the latest snapshot before the current frame, returns false if there is none
+    pub fn rewind(&mut self, emulator: &mut Emulator) -> bool {
+        self.truncate(emulator.get_frame());
+
+        match self.snapshots.back() {
+            Some(snapshot) => {
+                load_snapshot(emulator, snapshot);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Restores the state before the last instructions by re-executing them from an older snapshot
+    pub fn step_back(&mut self, emulator: &mut Emulator, count: u64) -> Result<(), String> {
+        let iteration = emulator.get_iteration().saturating_sub(count).max(1);
+        let snapshot = self
+            .snapshots
+            .iter()
+            .rev()
+            .find(|snapshot| snapshot.iteration <= iteration)
+            .ok_or_else(|| "No snapshot is old enough to step back".to_string())?;
+        load_snapshot(emulator, snapshot);
+
+        while emulator.get_iteration() < iteration {
+            if let Some(frame) = emulator.get_frame_start() {
+                if let Some((_, keys)) = self.inputs.iter().find(|input| input.0 == frame) {
+                    set_keys(&mut emulator.keypad, *keys);
+                }
+            }
+            emulator
+                .step(false, false)
+                .map_err(|error| error.to_string())?;
+        }
+
+        Ok(())
+    }
+
+    pub fn get_size(&self) -> usize {
+        self.size
+    }
+
+    // Discards snapshots and inputs from the frame on
+    fn truncate(&mut self, frame: u64) {
+        while self
+            .snapshots
+            .back()
+            .map_or(false, |snapshot| snapshot.frame >= frame)
+        {
+            let snapshot = self.snapshots.pop_back().unwrap();
+            self.size -= snapshot.state.len();
+        }
+        while self.inputs.back().map_or(false, |input| input.0 >= frame) {
+            self.inputs.pop_back();
+        }
+    }
+}
+
+fn load_snapshot(emulator: &mut Emulator, snapshot: &Snapshot) {
+    // Snapshots were created by the same emulator, so loading cannot fail
+    emulator
+        .load_state(&mut decompress(&snapshot.state).as_slice())
+        .unwrap();
+}
+
+// Save states consist mostly of zeros (memory and display), so runs of zeros are stored as
+// a zero followed by the length of the run
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut compressed = Vec::new();
+    let mut index = 0;
+    while index < data.len() {
+        if data[index] == 0 {
+            let run = data[index..]
+                .iter()
+                .take(255)
+                .take_while(|byte| **byte == 0)
+                .count();
+            compressed.push(0);
+            compressed.push(run as u8);
+            index += run;
+        } else {
+            compressed.push(data[index]);
+            index += 1;
+        }
+    }
+    compressed
+}
+
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut decompressed = Vec::new();
+    let mut bytes = data.iter();
+    while let Some(byte) = bytes.next() {
+        if *byte == 0 {
+            let run = *bytes.next().unwrap_or(&0) as usize;
+            decompressed.resize(decompressed.len() + run, 0);
+        } else {
+            decompressed.push(*byte);
+        }
+    }
+    decompressed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::*;
+    use crate::keypad::KeypadTrait;
+    use crate::quirks::Quirks;
+
+    fn instantiate_emulator() -> Emulator {
+        // ADD V0, 1; SKNP V3; ADD V1, 1; JP 0x200 (V1 counts the instructions while key 0 is pressed)
+        let program = vec![0x70, 0x01, 0xE3, 0xA1, 0x71, 0x01, 0x12, 0x00];
+        let mut emulator = Emulator::new(600.0, false, 0x200, Mode::Chip8, Quirks::default());
+        emulator.load_rom(&mut program.as_slice()).unwrap();
+        emulator
+    }
+
+    fn run(emulator: &mut Emulator, rewind: &mut RewindBuffer, frames: u64) {
+        for _ in 0..frames {
+            let frame = emulator.get_frame();
+            emulator.keypad.set_key(0x0, frame % 2 == 0);
+            rewind.record(emulator);
+            emulator.run_frame(false, false).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_compression() {
+        let data = [vec![1, 2, 0, 3], vec![0; 600], vec![4]].concat();
+
+        let compressed = compress(&data);
+
+        assert_eq!(compressed[..6], [1, 2, 0, 1, 3, 0]);
+        assert!(compressed.len() < 16);
+        assert_eq!(decompress(&compressed), data);
+    }
+
+    #[test]
+    fn test_rewind() {
+        let mut emulator = instantiate_emulator();
+        let mut rewind = RewindBuffer::new(1 << 20, 2);
+        run(&mut emulator, &mut rewind, 10);
+
+        let mut frames = Vec::new();
+        while rewind.rewind(&mut emulator) {
+            frames.push(emulator.get_frame());
+        }
+
+        assert_eq!(frames, vec![8, 6, 4, 2, 0]);
+        assert_eq!(emulator.cpu.get_register(0x0), 0);
+    }
+
+    #[test]
+    fn test_budget_drops_oldest_snapshots() {
+        let mut emulator = instantiate_emulator();
+        let mut rewind = RewindBuffer::new(1, 1);
+        run(&mut emulator, &mut rewind, 10);
+
+        assert!(rewind.rewind(&mut emulator));
+        assert_eq!(emulator.get_frame(), 9);
+        assert!(!rewind.rewind(&mut emulator));
+    }
+
+    #[test]
+    fn test_step_back_reexecutes_inputs() {
+        let mut emulator = instantiate_emulator();
+        let mut rewind = RewindBuffer::new(1 << 20, 4);
+        run(&mut emulator, &mut rewind, 10);
+        for _ in 0..5 {
+            emulator.step(false, false).unwrap();
+        }
+        let mut expected = Vec::new();
+        emulator.save_state(&mut expected).unwrap();
+        for _ in 0..20 {
+            emulator.step(false, false).unwrap();
+        }
+
+        rewind.step_back(&mut emulator, 20).unwrap();
+
+        let mut state = Vec::new();
+        emulator.save_state(&mut state).unwrap();
+        assert_eq!(state, expected);
+    }
+}
@@ -0,0 +1,226 @@
+use std::io::{Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::checksum::crc32;
+
+// File layout: magic, version, payload length, payload checksum, payload
+const STATE_MAGIC: [u8; 4] = *b"C8ST";
+const STATE_VERSION: u16 = 4;
+const STATE_HEADER_BYTES: usize = 4 + 2 + 4 + 4;
+
+pub const STATE_SLOTS: u8 = 10;
+
+// Serializes values into a little-endian byte buffer
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        StateWriter { data: Vec::new() }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_f32(&mut self, value: f32) {
+        self.write_u32(value.to_bits());
+    }
+
+    pub fn write_bytes(&mut self, values: &[u8]) {
+        self.data.extend_from_slice(values);
+    }
+
+    // Writes the header followed by the serialized values
+    pub fn finish(self, writer: &mut dyn Write) -> Result<(), Error> {
+        writer.write_all(&STATE_MAGIC)?;
+        writer.write_all(&STATE_VERSION.to_le_bytes())?;
+        writer.write_all(&(self.data.len() as u32).to_le_bytes())?;
+        writer.write_all(&crc32(&self.data).to_le_bytes())?;
+        writer.write_all(&self.data)
+    }
+}
+
+// Deserializes values written by a StateWriter
+pub struct StateReader {
+    data: Vec<u8>,
+    position: usize,
+}
+
+impl StateReader {
+    // Reads and validates the header, then buffers the payload
+    pub fn new(reader: &mut dyn Read) -> Result<StateReader, Error> {
+        let mut file = Vec::new();
+        reader.read_to_end(&mut file)?;
+
+        if file.len() < STATE_HEADER_BYTES || file[0..4] != STATE_MAGIC {
+            return Err(invalid_data("Not a save state file"));
+        }
+        let version = u16::from_le_bytes([file[4], file[5]]);
+        if version != STATE_VERSION {
+            return Err(invalid_data(&format!(
+                "Unsupported save state version {version} (expected {expected})",
+                version = version,
+                expected = STATE_VERSION
+            )));
+        }
+        let length = u32::from_le_bytes([file[6], file[7], file[8], file[9]]) as usize;
+        let checksum = u32::from_le_bytes([file[10], file[11], file[12], file[13]]);
+        let data = file.split_off(STATE_HEADER_BYTES);
+        if data.len() != length || crc32(&data) != checksum {
+            return Err(invalid_data("Save state is corrupted (checksum mismatch)"));
+        }
+
+        Ok(StateReader {
+            data: data,
+            position: 0,
+        })
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, Error> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, Error> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, Error> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, Error> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, Error> {
+        let mut value = [0; 8];
+        value.copy_from_slice(self.read_bytes(8)?);
+        Ok(u64::from_le_bytes(value))
+    }
+
+    pub fn read_f32(&mut self) -> Result<f32, Error> {
+        Ok(f32::from_bits(self.read_u32()?))
+    }
+
+    pub fn read_bytes(&mut self, count: usize) -> Result<&[u8], Error> {
+        if self.position + count > self.data.len() {
+            return Err(invalid_data("Save state is truncated"));
+        }
+
+        let bytes = &self.data[self.position..(self.position + count)];
+        self.position += count;
+        Ok(bytes)
+    }
+}
+
+pub fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+// Save states are stored next to the ROM, e.g. "pong.ch8" uses "pong.3.state" for slot 3
+pub fn get_slot_path(rom: &Path, slot: u8) -> PathBuf {
+    rom.with_extension(format!("{slot}.state", slot = slot))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{CpuDebugTrait, Mode};
+    use crate::display::*;
+    use crate::emulator::Emulator;
+    use crate::memory::MemoryTrait;
+    use crate::quirks::Quirks;
+
+    fn instantiate_emulator() -> Emulator {
+        let mut emulator = Emulator::new(600.0, false, 0x200, Mode::Chip8, Quirks::default());
+        emulator
+            .load_rom(&mut std::io::Cursor::new(vec![0x12, 0x00]))
+            .unwrap();
+        emulator
+    }
+
+    fn save(emulator: &Emulator) -> Vec<u8> {
+        let mut state = Vec::new();
+        emulator.save_state(&mut state).unwrap();
+        state
+    }
+
+    #[test]
+    fn test_save_and_load_state() {
+        let mut emulator = instantiate_emulator();
+        emulator.memory.write(0x300, 0xAB);
+        emulator.display.write_pixel(3, 4, true);
+        let state = save(&emulator);
+
+        let mut restored = instantiate_emulator();
+        restored
+            .load_state(&mut std::io::Cursor::new(state.clone()))
+            .unwrap();
+
+        assert_eq!(restored.memory.read(0x300), 0xAB);
+        assert_eq!(restored.display.read_pixel(3, 4), true);
+        assert_eq!(save(&restored), state);
+    }
+
+    #[test]
+    fn test_load_state_rejects_corrupted_data() {
+        let emulator = instantiate_emulator();
+        let mut state = save(&emulator);
+        let last = state.len() - 1;
+        state[last] ^= 0xFF;
+
+        let mut restored = instantiate_emulator();
+        let result = restored.load_state(&mut std::io::Cursor::new(state));
+
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_load_state_rolls_back_invalid_values() {
+        let mut emulator = instantiate_emulator();
+        emulator.cpu.set_pc(0x5000);
+        let state = save(&emulator);
+
+        let mut restored = instantiate_emulator();
+        let result = restored.load_state(&mut std::io::Cursor::new(state));
+
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(restored.cpu.get_pc(), 0x200);
+    }
+
+    #[test]
+    fn test_load_state_rejects_other_files() {
+        let mut emulator = instantiate_emulator();
+        let result = emulator.load_state(&mut std::io::Cursor::new(vec![0x12, 0x00]));
+
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_get_slot_path() {
+        assert_eq!(
+            get_slot_path(Path::new("roms/games/pong.ch8"), 3),
+            PathBuf::from("roms/games/pong.3.state")
+        );
+    }
+}
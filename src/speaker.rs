@@ -1,53 +1,423 @@
-extern crate rodio;
+use std::f32::consts::PI;
+use std::io::Error;
 
-use self::rodio::Source;
-use std::fs::File;
-use std::io::BufReader;
+use crate::savestate::{StateReader, StateWriter};
 
 #[cfg(test)]
 use mockall::{automock, predicate::*};
 
+pub const AUDIO_PATTERN_BYTES: usize = 16; // XO-CHIP 1-bit audio pattern (128 samples)
+pub const SAMPLE_RATE: u32 = 44100; // Hz
+const DEFAULT_PITCH: u8 = 64; // 4000 Hz playback rate
+
+/// Sound output, driven by the sound timer and the XO-CHIP audio instructions
 #[cfg_attr(test, automock)]
 pub trait SpeakerTrait {
-    fn queue_beep(&mut self);
-    fn flush_queue(&mut self);
-    fn clear_queue(&mut self);
+    fn set_tone(&mut self, active: bool); // the tone sounds while the sound timer is active
+    fn set_pattern(&mut self, pattern: Option<[u8; AUDIO_PATTERN_BYTES]>); // None selects the waveform
+    /// Playback rate of the audio pattern, 4000*2^((pitch-64)/48) Hz
+    fn set_pitch(&mut self, pitch: u8);
+    fn end_frame(&mut self); // called at the end of every 60 Hz frame of emulated time
+}
+
+/// Waveform of the buzzer tone
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+    Sawtooth,
+}
+
+impl Waveform {
+    /// Parses the name used on the command line
+    pub fn from_name(name: &str) -> Option<Waveform> {
+        match name {
+            "square" => Some(Waveform::Square),
+            "sine" => Some(Waveform::Sine),
+            "triangle" => Some(Waveform::Triangle),
+            "sawtooth" => Some(Waveform::Sawtooth),
+            _ => None,
+        }
+    }
+
+    // Sample at a position (0 to 1) within the period
+    fn get_sample(&self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square if phase < 0.5 => 1.0,
+            Waveform::Square => -1.0,
+            Waveform::Sine => (2.0 * PI * phase).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+        }
+    }
+}
+
+/// Sound of the buzzer, XO-CHIP audio patterns replace the waveform
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ToneConfig {
+    pub frequency: f32, // Hz
+    pub waveform: Waveform,
+    pub volume: f32, // 0 to 1
+}
+
+impl Default for ToneConfig {
+    fn default() -> ToneConfig {
+        ToneConfig {
+            frequency: 440.0,
+            waveform: Waveform::Square,
+            volume: 0.25,
+        }
+    }
+}
+
+/// Synthesises the buzzer output sample by sample
+pub struct ToneGenerator {
+    active: bool,
+    pattern: Option<[u8; AUDIO_PATTERN_BYTES]>,
+    pitch: u8,
+    phase: f32, // position within the period or the pattern (0 to 1)
+
+    // Configuration
+    config: ToneConfig,
+    sample_rate: u32,
+}
+
+impl SpeakerTrait for ToneGenerator {
+    fn set_tone(&mut self, active: bool) {
+        self.active = active;
+    }
+
+    fn set_pattern(&mut self, pattern: Option<[u8; AUDIO_PATTERN_BYTES]>) {
+        self.pattern = pattern;
+    }
+
+    fn set_pitch(&mut self, pitch: u8) {
+        self.pitch = pitch;
+    }
+
+    // Samples are pulled by the output, so the generator does not keep track of time
+    fn end_frame(&mut self) {}
+}
+
+impl ToneGenerator {
+    /// Creates a silent generator for the given sample rate in Hz
+    pub fn new(config: ToneConfig, sample_rate: u32) -> ToneGenerator {
+        ToneGenerator {
+            active: false,
+            pattern: None,
+            pitch: DEFAULT_PITCH,
+            phase: 0.0,
+
+            // Configuration
+            config: config,
+            sample_rate: sample_rate,
+        }
+    }
+
+    /// Returns the next sample, silence while the tone is off
+    pub fn next_sample(&mut self) -> f32 {
+        // Every tone starts at the beginning of the period
+        if !self.active {
+            self.phase = 0.0;
+            return 0.0;
+        }
+
+        let (sample, frequency) = match &self.pattern {
+            Some(pattern) => {
+                let bit = (self.phase * (AUDIO_PATTERN_BYTES * 8) as f32) as usize;
+                let sample = if pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 {
+                    1.0
+                } else {
+                    -1.0
+                };
+                let frequency = get_playback_rate(self.pitch) / (AUDIO_PATTERN_BYTES * 8) as f32;
+                (sample, frequency)
+            }
+            None => (
+                self.config.waveform.get_sample(self.phase),
+                self.config.frequency,
+            ),
+        };
+
+        self.phase = (self.phase + frequency / self.sample_rate as f32).fract();
+        sample * self.config.volume
+    }
 }
 
+/// Emulated speaker, forwards its state to the attached audio outputs
 pub struct Speaker {
-    play_beep: bool,
+    active: bool,
+    outputs: Vec<Box<dyn SpeakerTrait>>,
+
+    // XO-CHIP
+    pattern: Option<[u8; AUDIO_PATTERN_BYTES]>, // replaces the waveform once set
+    pitch: u8,
 }
 
 impl SpeakerTrait for Speaker {
-    fn queue_beep(&mut self) {
-        self.play_beep = true;
+    fn set_tone(&mut self, active: bool) {
+        self.active = active;
+        for output in &mut self.outputs {
+            output.set_tone(active);
+        }
     }
 
-    fn flush_queue(&mut self) {
-        if self.play_beep {
-            Speaker::play_sound("resources/beep.wav");
+    fn set_pattern(&mut self, pattern: Option<[u8; AUDIO_PATTERN_BYTES]>) {
+        self.pattern = pattern;
+        for output in &mut self.outputs {
+            output.set_pattern(pattern);
+        }
+    }
 
-            self.play_beep = false;
+    fn set_pitch(&mut self, pitch: u8) {
+        self.pitch = pitch;
+        for output in &mut self.outputs {
+            output.set_pitch(pitch);
         }
     }
 
-    fn clear_queue(&mut self) {
-        self.play_beep = false;
+    fn end_frame(&mut self) {
+        for output in &mut self.outputs {
+            output.end_frame();
+        }
     }
 }
 
 impl Speaker {
+    /// Creates a silent speaker without outputs
     pub fn new() -> Speaker {
-        println!("Initializing speaker");
+        Speaker {
+            active: false,
+            outputs: Vec::new(),
+
+            // XO-CHIP
+            pattern: None,
+            pitch: DEFAULT_PITCH,
+        }
+    }
+
+    /// Without an output, sound is not played
+    pub fn add_output(&mut self, mut output: Box<dyn SpeakerTrait>) {
+        output.set_tone(self.active);
+        output.set_pattern(self.pattern);
+        output.set_pitch(self.pitch);
+        self.outputs.push(output);
+    }
+
+    /// Whether the tone currently sounds
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Silences the speaker and restores the default waveform and pitch
+    pub fn reset(&mut self) {
+        self.active = false;
+        self.pattern = None;
+        self.pitch = DEFAULT_PITCH;
+        self.update_outputs();
+    }
+
+    /// The tone is not part of the state, it follows the sound timer of the CPU
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.pattern.is_some());
+        state.write_bytes(&self.pattern.unwrap_or([0; AUDIO_PATTERN_BYTES]));
+        state.write_u8(self.pitch);
+    }
+
+    /// Reads the state written by save_state and forwards it to the outputs
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), Error> {
+        let has_pattern = state.read_bool()?;
+        let mut pattern = [0; AUDIO_PATTERN_BYTES];
+        pattern.copy_from_slice(state.read_bytes(AUDIO_PATTERN_BYTES)?);
+        self.pattern = if has_pattern { Some(pattern) } else { None };
+        self.pitch = state.read_u8()?;
+        self.update_outputs();
+
+        Ok(())
+    }
 
-        Speaker { play_beep: false }
+    fn update_outputs(&mut self) {
+        for output in &mut self.outputs {
+            output.set_tone(self.active);
+            output.set_pattern(self.pattern);
+            output.set_pitch(self.pitch);
+        }
+    }
+}
+
+// Playback rate of the audio pattern in samples (bits) per second
+fn get_playback_rate(pitch: u8) -> f32 {
+    4000.0 * 2.0_f32.powf((pitch as f32 - 64.0) / 48.0)
+}
+
+#[cfg(feature = "rodio")]
+pub use self::audio::AudioOutput;
+
+#[cfg(feature = "rodio")]
+mod audio {
+    use rodio::{Sink, Source};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use super::*;
+
+    /// Persistent audio stream that plays the tone generator
+    pub struct AudioOutput {
+        generator: Arc<Mutex<ToneGenerator>>,
+        _sink: Sink, // the stream stops when the sink is dropped
+    }
+
+    impl SpeakerTrait for AudioOutput {
+        fn set_tone(&mut self, active: bool) {
+            self.generator.lock().unwrap().set_tone(active);
+        }
+
+        fn set_pattern(&mut self, pattern: Option<[u8; AUDIO_PATTERN_BYTES]>) {
+            self.generator.lock().unwrap().set_pattern(pattern);
+        }
+
+        fn set_pitch(&mut self, pitch: u8) {
+            self.generator.lock().unwrap().set_pitch(pitch);
+        }
+
+        // Played in wall clock time
+        fn end_frame(&mut self) {}
+    }
+
+    impl AudioOutput {
+        /// Returns None if there is no audio device
+        pub fn new(config: ToneConfig) -> Option<AudioOutput> {
+            let device = rodio::default_output_device()?;
+            let generator = Arc::new(Mutex::new(ToneGenerator::new(config, SAMPLE_RATE)));
+
+            let sink = Sink::new(&device);
+            sink.append(ToneSource {
+                generator: generator.clone(),
+            });
+
+            Some(AudioOutput {
+                generator: generator,
+                _sink: sink,
+            })
+        }
+    }
+
+    // Endless source that is read by the audio thread
+    struct ToneSource {
+        generator: Arc<Mutex<ToneGenerator>>,
+    }
+
+    impl Iterator for ToneSource {
+        type Item = f32;
+
+        fn next(&mut self) -> Option<f32> {
+            Some(self.generator.lock().unwrap().next_sample())
+        }
     }
 
-    fn play_sound(file_name: &str) {
-        let device = rodio::default_output_device().unwrap();
+    impl Source for ToneSource {
+        fn current_frame_len(&self) -> Option<usize> {
+            None
+        }
+
+        fn channels(&self) -> u16 {
+            1
+        }
+
+        fn sample_rate(&self) -> u32 {
+            SAMPLE_RATE
+        }
+
+        fn total_duration(&self) -> Option<Duration> {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instantiate_generator(waveform: Waveform) -> ToneGenerator {
+        let config = ToneConfig {
+            frequency: 1000.0,
+            waveform: waveform,
+            volume: 0.5,
+        };
+        ToneGenerator::new(config, 8000)
+    }
+
+    fn generate(generator: &mut ToneGenerator, count: usize) -> Vec<f32> {
+        (0..count).map(|_| generator.next_sample()).collect()
+    }
+
+    #[test]
+    fn test_tone_is_gated() {
+        let mut generator = instantiate_generator(Waveform::Square);
+        assert_eq!(generate(&mut generator, 4), vec![0.0; 4]);
+
+        generator.set_tone(true);
+        assert_eq!(
+            generate(&mut generator, 8),
+            vec![0.5, 0.5, 0.5, 0.5, -0.5, -0.5, -0.5, -0.5]
+        );
+
+        generator.set_tone(false);
+        assert_eq!(generate(&mut generator, 4), vec![0.0; 4]);
+    }
+
+    #[test]
+    fn test_waveforms() {
+        let mut generator = instantiate_generator(Waveform::Triangle);
+        generator.set_tone(true);
+        assert_eq!(generate(&mut generator, 4), vec![-0.5, -0.25, 0.0, 0.25]);
+
+        let mut generator = instantiate_generator(Waveform::Sine);
+        generator.set_tone(true);
+        let samples = generate(&mut generator, 3);
+        assert_eq!(samples[0], 0.0);
+        assert!((samples[2] - 0.5).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_pattern_replaces_waveform() {
+        let mut generator = instantiate_generator(Waveform::Sine);
+        let mut pattern = [0x00; AUDIO_PATTERN_BYTES];
+        pattern[0] = 0xF0;
+        generator.set_pattern(Some(pattern));
+        generator.set_pitch(64); // 4000 bits per second, 2 samples per bit
+        generator.set_tone(true);
+
+        let samples = generate(&mut generator, 10);
+
+        assert_eq!(samples, [vec![0.5; 8], vec![-0.5; 2]].concat());
+    }
+
+    #[test]
+    fn test_speaker_forwards_state_to_output() {
+        let mut speaker = Speaker::new();
+        speaker.set_tone(true);
+        speaker.set_pitch(100);
+
+        let mut output = MockSpeakerTrait::new();
+        output
+            .expect_set_tone()
+            .with(eq(true))
+            .times(1)
+            .return_const(());
+        output
+            .expect_set_pattern()
+            .with(eq(None))
+            .times(1)
+            .return_const(());
+        output
+            .expect_set_pitch()
+            .with(eq(100))
+            .times(1)
+            .return_const(());
+        speaker.add_output(Box::new(output));
 
-        let file = File::open(file_name).unwrap();
-        let source = rodio::Decoder::new(BufReader::new(file)).unwrap();
-        rodio::play_raw(&device, source.convert_samples());
+        assert!(speaker.is_active());
     }
 }
@@ -0,0 +1,117 @@
+const FRAME_RATE: f32 = 60.0;
+const TURBO_FACTOR: f32 = 4.0;
+const SLOW_MOTION_FACTOR: f32 = 0.25;
+
+// Wall clock speed of the emulation. The frame rate is scaled, so the clock rate and the timers
+// speed up and slow down together.
+pub struct Speed {
+    speed: f32, // configured multiplier
+    turbo: bool,
+    slow_motion: bool,
+    paused: bool,
+    advance_frame: bool, // runs a single frame while paused
+}
+
+impl Speed {
+    pub fn new(speed: f32) -> Speed {
+        Speed {
+            speed: speed,
+            turbo: false,
+            slow_motion: false,
+            paused: false,
+            advance_frame: false,
+        }
+    }
+
+    pub fn set_turbo(&mut self, turbo: bool) {
+        self.turbo = turbo;
+    }
+
+    pub fn toggle_slow_motion(&mut self) {
+        self.slow_motion = !self.slow_motion;
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    // Pauses the emulation after the next frame
+    pub fn advance_frame(&mut self) {
+        self.paused = true;
+        self.advance_frame = true;
+    }
+
+    // Returns false while paused, unless a single frame was requested
+    pub fn should_run_frame(&mut self) -> bool {
+        let advance_frame = self.advance_frame;
+        self.advance_frame = false;
+        !self.paused || advance_frame
+    }
+
+    pub fn get_factor(&self) -> f32 {
+        let mut factor = self.speed;
+        if self.turbo {
+            factor *= TURBO_FACTOR;
+        }
+        if self.slow_motion {
+            factor *= SLOW_MOTION_FACTOR;
+        }
+        factor
+    }
+
+    // Frames per wall clock second, while paused the frontend keeps polling at the normal rate
+    pub fn get_frame_rate(&self) -> f32 {
+        if self.paused && !self.advance_frame {
+            FRAME_RATE
+        } else {
+            FRAME_RATE * self.get_factor()
+        }
+    }
+
+    pub fn get_label(&self) -> String {
+        if self.paused {
+            "paused".to_string()
+        } else {
+            format!("{}%", (self.get_factor() * 100.0).round())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_factor() {
+        let mut speed = Speed::new(1.5);
+        assert_eq!(speed.get_factor(), 1.5);
+        assert_eq!(speed.get_frame_rate(), 90.0);
+
+        speed.set_turbo(true);
+        speed.toggle_slow_motion();
+        assert_eq!(speed.get_factor(), 1.5);
+
+        speed.set_turbo(false);
+        assert_eq!(speed.get_factor(), 0.375);
+        assert_eq!(speed.get_label(), "38%");
+    }
+
+    #[test]
+    fn test_pause_and_advance_frame() {
+        let mut speed = Speed::new(2.0);
+        assert!(speed.should_run_frame());
+
+        speed.toggle_pause();
+        assert!(!speed.should_run_frame());
+        assert_eq!(speed.get_frame_rate(), 60.0);
+        assert_eq!(speed.get_label(), "paused");
+
+        speed.advance_frame();
+        assert_eq!(speed.get_frame_rate(), 120.0);
+        assert!(speed.should_run_frame());
+        assert!(!speed.should_run_frame());
+
+        speed.toggle_pause();
+        assert!(speed.should_run_frame());
+    }
+}
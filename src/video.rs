@@ -0,0 +1,425 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Error, Write};
+use std::path::{Path, PathBuf};
+
+use crate::display::*;
+use crate::image::*;
+
+const FRAME_RATE: u64 = 60;
+const GIF_MIN_CODE_SIZE: u8 = 2; // 4 palette colors
+const GIF_MAX_CODE_SIZE: u8 = 12;
+const GIF_MIN_DELAY: u64 = 2; // centiseconds, most viewers slow down shorter delays
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VideoFormat {
+    Gif,
+    Png, // numbered sequence, e.g. "clip.00042.png"
+    Y4m,
+}
+
+impl VideoFormat {
+    // Determines the format from the file extension
+    pub fn from_path(path: &Path) -> Option<VideoFormat> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "gif" => Some(VideoFormat::Gif),
+            "png" => Some(VideoFormat::Png),
+            "y4m" => Some(VideoFormat::Y4m),
+            _ => None,
+        }
+    }
+}
+
+// Captures the display once per emulated frame. The video has the size of the window,
+// high resolution frames are scaled by half the display scale.
+pub struct VideoRecorder {
+    path: PathBuf,
+    writer: Option<BufWriter<File>>, // None for PNG sequences
+    frame_count: u64,
+
+    // GIF frames are only written once the image changes, until then the delay is unknown
+    pending_frame: Option<Vec<u8>>,
+    written_time: u64, // centiseconds
+
+    // Configuration
+    format: VideoFormat,
+    width: usize,
+    height: usize,
+}
+
+impl VideoRecorder {
+    pub fn new(path: &Path, scale: usize) -> Result<VideoRecorder, Error> {
+        let format = VideoFormat::from_path(path).unwrap();
+        let (width, height) = (DISPLAY_WIDTH * scale, DISPLAY_HEIGHT * scale);
+
+        let writer = match format {
+            VideoFormat::Gif => {
+                let mut writer = BufWriter::new(File::create(path)?);
+                write_gif_header(&mut writer, width, height, &PALETTE)?;
+                Some(writer)
+            }
+            VideoFormat::Y4m => {
+                let mut writer = BufWriter::new(File::create(path)?);
+                write!(
+                    writer,
+                    "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444 XCOLORRANGE=FULL\n",
+                    width, height, FRAME_RATE
+                )?;
+                Some(writer)
+            }
+            VideoFormat::Png => None,
+        };
+
+        Ok(VideoRecorder {
+            path: path.to_path_buf(),
+            writer: writer,
+            frame_count: 0,
+
+            pending_frame: None,
+            written_time: 0,
+
+            // Configuration
+            format: format,
+            width: width,
+            height: height,
+        })
+    }
+
+    pub fn add_frame(&mut self, display: &Display) -> Result<(), Error> {
+        let pixels = display.get_scaled_pixels(self.width, self.height);
+
+        match (self.format, &mut self.writer) {
+            (VideoFormat::Gif, Some(writer)) => {
+                // Images that would be shown too briefly are replaced by the next one
+                let time = self.frame_count * 100 / FRAME_RATE;
+                match &self.pending_frame {
+                    Some(pending) if *pending == pixels => (),
+                    Some(pending) if time - self.written_time >= GIF_MIN_DELAY => {
+                        let delay = time - self.written_time;
+                        write_gif_frame(writer, self.width, self.height, pending, delay)?;
+                        self.written_time = time;
+                        self.pending_frame = Some(pixels);
+                    }
+                    _ => self.pending_frame = Some(pixels),
+                }
+            }
+            (VideoFormat::Y4m, Some(writer)) => {
+                writer.write_all(b"FRAME\n")?;
+                writer.write_all(&to_ycbcr(&to_rgb(&pixels, &PALETTE)))?;
+            }
+            _ => {
+                let path = self
+                    .path
+                    .with_extension(format!("{:05}.png", self.frame_count));
+                let mut writer = BufWriter::new(File::create(path)?);
+                write_image(
+                    &mut writer,
+                    ImageFormat::Png,
+                    self.width,
+                    self.height,
+                    &pixels,
+                    &PALETTE,
+                )?;
+            }
+        }
+
+        self.frame_count += 1;
+        Ok(())
+    }
+
+    // Writes the remaining GIF frame and the trailer
+    pub fn finish(mut self) -> Result<(), Error> {
+        if let Some(mut writer) = self.writer.take() {
+            if let Some(pending) = &self.pending_frame {
+                let time = self.frame_count * 100 / FRAME_RATE;
+                let delay = (time - self.written_time).max(GIF_MIN_DELAY);
+                write_gif_frame(&mut writer, self.width, self.height, pending, delay)?;
+            }
+            if self.format == VideoFormat::Gif {
+                writer.write_all(&[0x3B])?;
+            }
+            writer.flush()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn get_frame_count(&self) -> u64 {
+        self.frame_count
+    }
+}
+
+// Header with a global color table and an infinite loop
+fn write_gif_header(
+    writer: &mut dyn Write,
+    width: usize,
+    height: usize,
+    palette: &[(u8, u8, u8); 4],
+) -> Result<(), Error> {
+    writer.write_all(b"GIF89a")?;
+    writer.write_all(&(width as u16).to_le_bytes())?;
+    writer.write_all(&(height as u16).to_le_bytes())?;
+    writer.write_all(&[0x91, 0, 0])?; // Global color table with 4 colors, background, aspect ratio
+    for (red, green, blue) in palette {
+        writer.write_all(&[*red, *green, *blue])?;
+    }
+
+    writer.write_all(&[0x21, 0xFF, 0x0B])?;
+    writer.write_all(b"NETSCAPE2.0")?;
+    writer.write_all(&[0x03, 0x01, 0x00, 0x00, 0x00])
+}
+
+fn write_gif_frame(
+    writer: &mut dyn Write,
+    width: usize,
+    height: usize,
+    pixels: &[u8],
+    delay: u64,
+) -> Result<(), Error> {
+    // Graphic control extension
+    writer.write_all(&[0x21, 0xF9, 0x04, 0x00])?;
+    writer.write_all(&(delay as u16).to_le_bytes())?;
+    writer.write_all(&[0x00, 0x00])?;
+
+    // Image descriptor, the image covers the whole screen
+    writer.write_all(&[0x2C, 0x00, 0x00, 0x00, 0x00])?;
+    writer.write_all(&(width as u16).to_le_bytes())?;
+    writer.write_all(&(height as u16).to_le_bytes())?;
+    writer.write_all(&[0x00])?;
+
+    // Image data in sub-blocks of up to 255 bytes
+    writer.write_all(&[GIF_MIN_CODE_SIZE])?;
+    for block in lzw_encode(pixels, GIF_MIN_CODE_SIZE).chunks(255) {
+        writer.write_all(&[block.len() as u8])?;
+        writer.write_all(block)?;
+    }
+    writer.write_all(&[0x00])
+}
+
+// Variable length LZW as used by GIF, the codes are packed starting with the least significant bit
+fn lzw_encode(pixels: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear_code = 1u16 << min_code_size;
+    let end_code = clear_code + 1;
+
+    let mut data = Vec::new();
+    let mut buffer: u32 = 0;
+    let mut buffered_bits = 0;
+    let mut write_code = |code: u16, code_size: u8| {
+        buffer |= (code as u32) << buffered_bits;
+        buffered_bits += code_size;
+        while buffered_bits >= 8 {
+            data.push(buffer as u8);
+            buffer >>= 8;
+            buffered_bits -= 8;
+        }
+    };
+
+    let mut codes: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next_code = end_code + 1;
+    let mut code_size = min_code_size + 1;
+    write_code(clear_code, code_size);
+
+    let mut prefix: Option<u16> = None;
+    for pixel in pixels {
+        let current = match prefix {
+            Some(current) => current,
+            None => {
+                prefix = Some(*pixel as u16);
+                continue;
+            }
+        };
+        if let Some(code) = codes.get(&(current, *pixel)) {
+            prefix = Some(*code);
+            continue;
+        }
+
+        write_code(current, code_size);
+        if next_code < 1 << GIF_MAX_CODE_SIZE {
+            codes.insert((current, *pixel), next_code);
+            next_code += 1;
+            // The decoder adds its codes one step later, so the size grows after the next code
+            if next_code > 1 << code_size && code_size < GIF_MAX_CODE_SIZE {
+                code_size += 1;
+            }
+        } else {
+            write_code(clear_code, code_size);
+            codes.clear();
+            next_code = end_code + 1;
+            code_size = min_code_size + 1;
+        }
+        prefix = Some(*pixel as u16);
+    }
+
+    if let Some(current) = prefix {
+        write_code(current, code_size);
+        if next_code == 1 << code_size && code_size < GIF_MAX_CODE_SIZE {
+            code_size += 1;
+        }
+    }
+    write_code(end_code, code_size);
+    if buffered_bits > 0 {
+        data.push(buffer as u8);
+    }
+    data
+}
+
+// Planar full range BT.601 YCbCr without chroma subsampling
+fn to_ycbcr(rgb: &[u8]) -> Vec<u8> {
+    let pixel_count = rgb.len() / 3;
+    let mut planes = vec![0; pixel_count * 3];
+    for (index, pixel) in rgb.chunks(3).enumerate() {
+        let (red, green, blue) = (pixel[0] as f32, pixel[1] as f32, pixel[2] as f32);
+        let y = 0.299 * red + 0.587 * green + 0.114 * blue;
+        let cb = 128.0 - 0.168736 * red - 0.331264 * green + 0.5 * blue;
+        let cr = 128.0 + 0.5 * red - 0.418688 * green - 0.081312 * blue;
+
+        planes[index] = y.round() as u8;
+        planes[pixel_count + index] = cb.round() as u8;
+        planes[pixel_count * 2 + index] = cr.round() as u8;
+    }
+    planes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::DisplayTrait;
+    use crate::quirks::Quirks;
+    use std::env;
+    use std::fs;
+
+    // Reference decoder, the code size grows once all codes of the current size are assigned
+    fn lzw_decode(data: &[u8], min_code_size: u8) -> Vec<u8> {
+        let clear_code = 1u16 << min_code_size;
+        let end_code = clear_code + 1;
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let mut code_size = min_code_size + 1;
+        let mut previous: Option<u16> = None;
+        let mut pixels = Vec::new();
+
+        let mut position = 0;
+        loop {
+            let mut code = 0;
+            for bit in 0..code_size as usize {
+                let index = position + bit;
+                code |= (((data[index / 8] >> (index % 8)) & 1) as u16) << bit;
+            }
+            position += code_size as usize;
+
+            if code == clear_code {
+                table = (0..clear_code).map(|color| vec![color as u8]).collect();
+                table.push(Vec::new());
+                table.push(Vec::new());
+                code_size = min_code_size + 1;
+                previous = None;
+                continue;
+            }
+            if code == end_code {
+                return pixels;
+            }
+
+            let entry = match previous {
+                Some(previous) => {
+                    let mut entry = table[previous as usize].clone();
+                    let first = if (code as usize) < table.len() {
+                        table[code as usize][0]
+                    } else {
+                        entry[0]
+                    };
+                    entry.push(first);
+                    if table.len() < 1 << GIF_MAX_CODE_SIZE {
+                        table.push(entry);
+                    }
+                    if table.len() == 1 << code_size && code_size < GIF_MAX_CODE_SIZE {
+                        code_size += 1;
+                    }
+                    table[code as usize].clone()
+                }
+                None => table[code as usize].clone(),
+            };
+            pixels.extend_from_slice(&entry);
+            previous = Some(code);
+        }
+    }
+
+    fn instantiate_display() -> Display {
+        Display::new(Quirks::default())
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(
+            VideoFormat::from_path(Path::new("clip.GIF")),
+            Some(VideoFormat::Gif)
+        );
+        assert_eq!(
+            VideoFormat::from_path(Path::new("clip.y4m")),
+            Some(VideoFormat::Y4m)
+        );
+        assert_eq!(VideoFormat::from_path(Path::new("clip.avi")), None);
+    }
+
+    #[test]
+    fn test_lzw_round_trip() {
+        // Enough pixels to fill the code table several times
+        let pixels: Vec<u8> = (0..20000_u32)
+            .map(|index| ((index * index / 7 + index / 13) % 4) as u8)
+            .collect();
+
+        let encoded = lzw_encode(&pixels, GIF_MIN_CODE_SIZE);
+
+        assert_eq!(lzw_decode(&encoded, GIF_MIN_CODE_SIZE), pixels);
+        assert_eq!(lzw_decode(&lzw_encode(&[3], 2), 2), vec![3]);
+    }
+
+    #[test]
+    fn test_gif_merges_unchanged_frames() {
+        let path = env::temp_dir().join("chip-8-emulator-test.gif");
+        let mut display = instantiate_display();
+        let mut recorder = VideoRecorder::new(&path, 1).unwrap();
+        for frame in 0..12 {
+            if frame == 6 {
+                display.draw_sprite(0, 0, &[0xFF]);
+            }
+            recorder.add_frame(&display).unwrap();
+        }
+        recorder.finish().unwrap();
+
+        let gif = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let delays: Vec<u16> = gif
+            .windows(4)
+            .enumerate()
+            .filter(|(_, window)| *window == [0x21, 0xF9, 0x04, 0x00])
+            .map(|(index, _)| u16::from_le_bytes([gif[index + 4], gif[index + 5]]))
+            .collect();
+        assert_eq!(&gif[..6], b"GIF89a");
+        assert_eq!(delays, vec![10, 10]);
+        assert_eq!(gif[gif.len() - 1], 0x3B);
+    }
+
+    #[test]
+    fn test_y4m_frames() {
+        let path = env::temp_dir().join("chip-8-emulator-test.y4m");
+        let display = instantiate_display();
+        let mut recorder = VideoRecorder::new(&path, 2).unwrap();
+        recorder.add_frame(&display).unwrap();
+        recorder.add_frame(&display).unwrap();
+        assert_eq!(recorder.get_frame_count(), 2);
+        recorder.finish().unwrap();
+
+        let video = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let header = b"YUV4MPEG2 W128 H64 F60:1 Ip A1:1 C444 XCOLORRANGE=FULL\n";
+        assert_eq!(&video[..header.len()], &header[..]);
+        assert_eq!(video.len(), header.len() + 2 * (6 + 128 * 64 * 3));
+    }
+
+    #[test]
+    fn test_ycbcr() {
+        assert_eq!(
+            to_ycbcr(&[255, 255, 255, 0, 0, 0]),
+            vec![255, 0, 128, 128, 128, 128]
+        );
+    }
+}
@@ -0,0 +1,133 @@
+use std::io::{Error, Seek, SeekFrom, Write};
+
+use crate::speaker::*;
+
+const FRAME_RATE: u32 = 60;
+const SAMPLES_PER_FRAME: u32 = SAMPLE_RATE / FRAME_RATE;
+const HEADER_BYTES: u32 = 44;
+
+// Audio output that renders the buzzer to a 16-bit mono WAV file in emulated time,
+// so runs produce the same file independent of the wall clock speed
+pub struct WavWriter<W: Write + Seek> {
+    generator: ToneGenerator,
+    writer: Option<W>, // None once finished or after a write error
+    sample_count: u32,
+}
+
+impl<W: Write + Seek> SpeakerTrait for WavWriter<W> {
+    fn set_tone(&mut self, active: bool) {
+        self.generator.set_tone(active);
+    }
+
+    fn set_pattern(&mut self, pattern: Option<[u8; AUDIO_PATTERN_BYTES]>) {
+        self.generator.set_pattern(pattern);
+    }
+
+    fn set_pitch(&mut self, pitch: u8) {
+        self.generator.set_pitch(pitch);
+    }
+
+    fn end_frame(&mut self) {
+        let mut samples = Vec::with_capacity(SAMPLES_PER_FRAME as usize * 2);
+        for _ in 0..SAMPLES_PER_FRAME {
+            let sample = (self.generator.next_sample() * i16::MAX as f32) as i16;
+            samples.extend_from_slice(&sample.to_le_bytes());
+        }
+
+        if let Some(writer) = &mut self.writer {
+            match writer.write_all(&samples) {
+                Ok(()) => self.sample_count += SAMPLES_PER_FRAME,
+                Err(error) => {
+                    println!("Could not write WAV file: {}", error);
+                    self.writer = None;
+                }
+            }
+        }
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut writer: W, config: ToneConfig) -> Result<WavWriter<W>, Error> {
+        write_header(&mut writer, 0)?;
+
+        Ok(WavWriter {
+            generator: ToneGenerator::new(config, SAMPLE_RATE),
+            writer: Some(writer),
+            sample_count: 0,
+        })
+    }
+
+    // Completes the header with the length of the recording, also done when the writer is dropped
+    pub fn finish(&mut self) -> Result<(), Error> {
+        if let Some(mut writer) = self.writer.take() {
+            writer.seek(SeekFrom::Start(0))?;
+            write_header(&mut writer, self.sample_count)?;
+            writer.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> Drop for WavWriter<W> {
+    fn drop(&mut self) {
+        if let Err(error) = self.finish() {
+            println!("Could not write WAV file: {}", error);
+        }
+    }
+}
+
+fn write_header(writer: &mut dyn Write, sample_count: u32) -> Result<(), Error> {
+    let data_bytes = sample_count * 2;
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(HEADER_BYTES - 8 + data_bytes).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?; // chunk size
+    writer.write_all(&1u16.to_le_bytes())?; // PCM
+    writer.write_all(&1u16.to_le_bytes())?; // channels
+    writer.write_all(&SAMPLE_RATE.to_le_bytes())?;
+    writer.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?; // bytes per second
+    writer.write_all(&2u16.to_le_bytes())?; // bytes per sample
+    writer.write_all(&16u16.to_le_bytes())?; // bits per sample
+
+    writer.write_all(b"data")?;
+    writer.write_all(&data_bytes.to_le_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn get_u32(data: &[u8], offset: usize) -> u32 {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&data[offset..offset + 4]);
+        u32::from_le_bytes(bytes)
+    }
+
+    #[test]
+    fn test_samples_follow_emulated_frames() {
+        let mut data = Cursor::new(Vec::new());
+        let mut wav = WavWriter::new(&mut data, ToneConfig::default()).unwrap();
+
+        wav.end_frame();
+        wav.set_tone(true);
+        wav.end_frame();
+        wav.end_frame();
+        wav.finish().unwrap();
+        drop(wav);
+
+        let data = data.into_inner();
+        let samples = &data[HEADER_BYTES as usize..];
+        assert_eq!(&data[..4], b"RIFF");
+        assert_eq!(get_u32(&data, 4), data.len() as u32 - 8);
+        assert_eq!(get_u32(&data, 24), SAMPLE_RATE);
+        assert_eq!(get_u32(&data, 40), samples.len() as u32);
+        assert_eq!(samples.len(), 3 * 735 * 2);
+        assert!(samples[..735 * 2].iter().all(|byte| *byte == 0));
+        assert!(samples[735 * 2..].iter().any(|byte| *byte != 0));
+    }
+}
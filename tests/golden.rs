@@ -0,0 +1,203 @@
+// Golden image regression tests over the bundled ROMs.
+// Every ROM runs headless with a fixed seed for a scripted number of frames, afterwards the display is
+// compared against tests/golden/<directory>/<rom>.pbm. Mismatching images are written to target/golden.
+// Run with UPDATE_GOLDEN=1 to regenerate the golden images after an intended change.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+use chip_8_emulator::cpu::*;
+use chip_8_emulator::emulator::Emulator;
+use chip_8_emulator::error::EmulatorError;
+use chip_8_emulator::image::ImageFormat;
+use chip_8_emulator::keypad::KeypadTrait;
+use chip_8_emulator::quirks::Quirks;
+
+const ROM_DIRECTORIES: [&str; 3] = ["demos", "games", "programs"];
+const SEED: u64 = 0xC8;
+const CLOCK_RATE: f32 = 600.0;
+const DEFAULT_FRAMES: usize = 300;
+
+// Per ROM overrides from tests/golden/script.txt
+struct Script {
+    frames: usize,
+    inputs: Vec<(usize, u8, bool)>, // frame, key, pressed
+    ignore_unknown_instructions: bool,
+}
+
+impl Default for Script {
+    fn default() -> Script {
+        Script {
+            frames: DEFAULT_FRAMES,
+            inputs: Vec::new(),
+            ignore_unknown_instructions: false,
+        }
+    }
+}
+
+// Each line contains the ROM, the number of frames and optional key events or flags, e.g.
+// "games/pong.ch8 600 60:+1 90:-1" presses key 1 at frame 60 and releases it at frame 90.
+// The flag "ignore-unknown-instructions" keeps ROMs running that execute data.
+fn parse_scripts(text: &str) -> HashMap<String, Script> {
+    let mut scripts = HashMap::new();
+
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut tokens = line.split_whitespace();
+        let rom = tokens.next().unwrap().to_string();
+        let frames = tokens
+            .next()
+            .and_then(|frames| frames.parse().ok())
+            .unwrap_or_else(|| panic!("Missing frame count for {}", rom));
+
+        let mut script = Script {
+            frames: frames,
+            ..Script::default()
+        };
+        for token in tokens {
+            if token == "ignore-unknown-instructions" {
+                script.ignore_unknown_instructions = true;
+            } else {
+                let input = parse_input(token);
+                script
+                    .inputs
+                    .push(input.unwrap_or_else(|| panic!("Invalid input {}", token)));
+            }
+        }
+
+        scripts.insert(rom, script);
+    }
+
+    scripts
+}
+
+fn parse_input(token: &str) -> Option<(usize, u8, bool)> {
+    let mut parts = token.splitn(2, ':');
+    let frame = parts.next()?.parse().ok()?;
+    let event = parts.next()?;
+    let pressed = match &event[..1] {
+        "+" => true,
+        "-" => false,
+        _ => return None,
+    };
+    let key = u8::from_str_radix(&event[1..], 16)
+        .ok()
+        .filter(|key| *key < 16)?;
+
+    Some((frame, key, pressed))
+}
+
+fn run(rom: &Path, script: &Script) -> Result<Vec<u8>, EmulatorError> {
+    let mut emulator = Emulator::new(
+        CLOCK_RATE,
+        script.ignore_unknown_instructions,
+        0x200,
+        Mode::Chip8,
+        Quirks::default(),
+    );
+    emulator.cpu.set_seed(SEED);
+    emulator.load_rom(&mut File::open(rom)?)?;
+
+    for frame in 0..script.frames {
+        for (_, key, pressed) in script.inputs.iter().filter(|input| input.0 == frame) {
+            emulator.keypad.set_key(*key, *pressed);
+        }
+        emulator.run_frame(false, false)?;
+    }
+
+    let mut image = Vec::new();
+    emulator
+        .display
+        .save_screenshot(&mut image, ImageFormat::Pbm, 1)
+        .unwrap();
+    Ok(image)
+}
+
+#[test]
+fn test_golden_images() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let golden_directory = root.join("tests/golden");
+    let actual_directory = root.join("target/golden");
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+
+    let scripts =
+        parse_scripts(&fs::read_to_string(golden_directory.join("script.txt")).unwrap_or_default());
+    let default_script = Script::default();
+
+    let mut roms: Vec<PathBuf> = Vec::new();
+    for directory in ROM_DIRECTORIES.iter() {
+        for entry in fs::read_dir(root.join("roms").join(directory)).unwrap() {
+            roms.push(entry.unwrap().path());
+        }
+    }
+    roms.sort();
+
+    let mut failures = Vec::new();
+    for rom in roms.iter() {
+        let name = rom
+            .strip_prefix(root.join("roms"))
+            .unwrap()
+            .to_string_lossy()
+            .replace('\\', "/");
+        let script = scripts.get(&name).unwrap_or(&default_script);
+        let golden = golden_directory.join(&name).with_extension("pbm");
+
+        let image = match panic::catch_unwind(AssertUnwindSafe(|| run(rom, script))) {
+            Ok(Ok(image)) => image,
+            Ok(Err(error)) => {
+                failures.push(format!("{}: emulation failed: {}", name, error));
+                continue;
+            }
+            Err(_) => {
+                failures.push(format!("{}: emulation panicked", name));
+                continue;
+            }
+        };
+
+        if update {
+            fs::create_dir_all(golden.parent().unwrap()).unwrap();
+            fs::write(&golden, &image).unwrap();
+            continue;
+        }
+
+        match fs::read(&golden) {
+            Ok(expected) if expected == image => (),
+            Ok(_) => {
+                let actual = actual_directory.join(&name).with_extension("pbm");
+                fs::create_dir_all(actual.parent().unwrap()).unwrap();
+                fs::write(&actual, &image).unwrap();
+                failures.push(format!(
+                    "{}: display differs from the golden image, see {}",
+                    name,
+                    actual.display()
+                ));
+            }
+            Err(_) => failures.push(format!("{}: golden image is missing", name)),
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} of {} ROMs diverged (run with UPDATE_GOLDEN=1 to regenerate):\n{}",
+        failures.len(),
+        roms.len(),
+        failures.join("\n")
+    );
+}
+
+#[test]
+fn test_parse_scripts() {
+    let scripts =
+        parse_scripts("# Comment\n\ngames/pong.ch8 600 60:+1 90:-A ignore-unknown-instructions\n");
+    let script = &scripts["games/pong.ch8"];
+
+    assert_eq!(script.frames, 600);
+    assert_eq!(script.inputs, vec![(60, 0x1, true), (90, 0xA, false)]);
+    assert_eq!(script.ignore_unknown_instructions, true);
+}